@@ -0,0 +1,140 @@
+use crate::chengine::*;
+
+//one bit per square, a1 is bit 0 and h8 is bit 63
+pub type Bitboard = u64;
+
+pub fn side(color: Color) -> usize {
+    match color {
+        Color::White => 0,
+        Color::Black => 1,
+    }
+}
+
+pub fn index(square: &Square) -> usize {
+    square.y as usize * 8 + square.x as usize
+}
+
+pub fn bit(square: &Square) -> Bitboard {
+    1 << index(square)
+}
+
+//the set squares from a1 upwards
+pub fn squares(mut bitboard: Bitboard) -> impl Iterator<Item = Square> {
+    std::iter::from_fn(move || {
+        if bitboard == 0 {
+            return None;
+        }
+        let index = bitboard.trailing_zeros() as u8;
+        bitboard &= bitboard - 1;
+        Some(Square {
+            x: index % 8,
+            y: index / 8,
+        })
+    })
+}
+
+const fn leaper(deltas: &[(i8, i8)]) -> [Bitboard; 64] {
+    let mut table = [0; 64];
+    let mut sq = 0;
+    while sq < 64 {
+        let (x, y) = ((sq % 8) as i8, (sq / 8) as i8);
+        let mut i = 0;
+        while i < deltas.len() {
+            let (tx, ty) = (x + deltas[i].0, y + deltas[i].1);
+            if 0 <= tx && tx < 8 && 0 <= ty && ty < 8 {
+                table[sq] |= 1 << (ty * 8 + tx);
+            }
+            i += 1;
+        }
+        sq += 1;
+    }
+    table
+}
+
+pub const KNIGHT_ATTACKS: [Bitboard; 64] = leaper(&[
+    (1, 2),
+    (-1, 2),
+    (1, -2),
+    (-1, -2),
+    (2, 1),
+    (-2, 1),
+    (2, -1),
+    (-2, -1),
+]);
+pub const KING_ATTACKS: [Bitboard; 64] = leaper(&[
+    (1, 0),
+    (-1, 0),
+    (0, -1),
+    (0, 1),
+    (1, 1),
+    (1, -1),
+    (-1, -1),
+    (-1, 1),
+]);
+//squares a pawn of each side attacks from a square
+pub const PAWN_ATTACKS: [[Bitboard; 64]; 2] =
+    [leaper(&[(1, 1), (-1, 1)]), leaper(&[(1, -1), (-1, -1)])];
+
+//the first four directions run towards higher bits, the rest towards lower ones
+const DIRECTIONS: [(i8, i8); 8] = [
+    (0, 1),
+    (1, 1),
+    (1, 0),
+    (-1, 1),
+    (0, -1),
+    (-1, -1),
+    (-1, 0),
+    (1, -1),
+];
+
+const fn rays() -> [[Bitboard; 64]; 8] {
+    let mut table = [[0; 64]; 8];
+    let mut dir = 0;
+    while dir < 8 {
+        let mut sq = 0;
+        while sq < 64 {
+            let (mut x, mut y) = ((sq % 8) as i8, (sq / 8) as i8);
+            loop {
+                x += DIRECTIONS[dir].0;
+                y += DIRECTIONS[dir].1;
+                if x < 0 || x >= 8 || y < 0 || y >= 8 {
+                    break;
+                }
+                table[dir][sq] |= 1 << (y * 8 + x);
+            }
+            sq += 1;
+        }
+        dir += 1;
+    }
+    table
+}
+
+const RAYS: [[Bitboard; 64]; 8] = rays();
+
+//classical sliding attacks, each ray is cut off behind its first blocker
+fn ray_attacks(dir: usize, sq: usize, occupied: Bitboard) -> Bitboard {
+    let ray = RAYS[dir][sq];
+    let blockers = ray & occupied;
+    if blockers == 0 {
+        return ray;
+    }
+    let first = match dir < 4 {
+        true => blockers.trailing_zeros(),
+        false => 63 - blockers.leading_zeros(),
+    };
+    ray ^ RAYS[dir][first as usize]
+}
+
+pub fn rook_attacks(sq: usize, occupied: Bitboard) -> Bitboard {
+    ray_attacks(0, sq, occupied)
+        | ray_attacks(2, sq, occupied)
+        | ray_attacks(4, sq, occupied)
+        | ray_attacks(6, sq, occupied)
+}
+
+pub fn bishop_attacks(sq: usize, occupied: Bitboard) -> Bitboard {
+    ray_attacks(1, sq, occupied)
+        | ray_attacks(3, sq, occupied)
+        | ray_attacks(5, sq, occupied)
+        | ray_attacks(7, sq, occupied)
+}
@@ -1,15 +1,37 @@
 use crate::chengine::*;
 use std::fmt;
 
-pub type MoveData = (i32, Piece, Option<Piece>, bool, CastleMoveData);
+//what exec_move hands back so unexec_move can take the move back
+#[derive(Debug, Clone, Copy)]
+pub struct UndoInfo {
+    //change in material, from white's point of view
+    pub points: i32,
+    //the piece as it stood before moving, still a pawn after a promotion
+    pub moved: Piece,
+    pub taken: Option<Piece>,
+    pub castle: CastleMoveData,
+    pub en_passant: bool,
+    pub state: BoardState,
+}
 
-#[derive(Clone)]
+//everything exec_move overwrites that unexec_move can't work out again
+#[derive(Debug, Clone, Copy)]
+pub struct BoardState {
+    pub en_passant: Option<Square>,
+    pub halfmove_clock: u16,
+    pub castle_white: CastleInfo,
+    pub castle_black: CastleInfo,
+    pub hash: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct CastleInfo {
     pub kingside: bool,
     pub queenside: bool,
 }
 
 impl CastleInfo {
+    #[allow(dead_code)]
     pub fn both() -> Self {
         Self {
             kingside: true,
@@ -25,7 +47,69 @@ impl CastleInfo {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum FenError {
+    WrongRankCount(usize),
+    BadRankLength(u8),
+    UnknownPiece(char),
+    MissingKing(Color),
+    MultipleKings(Color),
+    PawnOnBackRank(Square),
+    BadSideToMove(String),
+    BadCastlingRights(String),
+    InconsistentCastlingRights(char),
+    BadEnPassant(String),
+    BadClock(String),
+    ExtraField(String),
+    OpponentInCheck,
+}
+
+impl fmt::Display for FenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FenError::WrongRankCount(count) => write!(f, "expected 8 ranks, found {}", count),
+            FenError::BadRankLength(rank) => write!(f, "rank {} does not have 8 squares", rank),
+            FenError::UnknownPiece(char) => write!(f, "unknown piece '{}'", char),
+            FenError::MissingKing(color) => write!(f, "no {:?} king on board", color),
+            FenError::MultipleKings(color) => write!(f, "more than one {:?} king", color),
+            FenError::PawnOnBackRank(sq) => write!(f, "pawn on back rank at {}", sq),
+            FenError::BadSideToMove(side) => write!(f, "invalid side to move '{}'", side),
+            FenError::BadCastlingRights(rights) => {
+                write!(f, "invalid castling rights '{}'", rights)
+            }
+            FenError::InconsistentCastlingRights(right) => write!(
+                f,
+                "castling right '{}' without king and rook on their starting squares",
+                right
+            ),
+            FenError::BadEnPassant(sq) => write!(f, "invalid en passant square '{}'", sq),
+            FenError::BadClock(clock) => write!(f, "invalid move clock '{}'", clock),
+            FenError::ExtraField(field) => {
+                write!(f, "unexpected field '{}' after the clocks", field)
+            }
+            FenError::OpponentInCheck => write!(f, "side not to move is in check"),
+        }
+    }
+}
+
+impl std::error::Error for FenError {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DrawReason {
+    FiftyMoves,
+    Repetition,
+    InsufficientMaterial,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    Ongoing,
+    Checkmate(Color),
+    Stalemate,
+    Draw(DrawReason),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum CastleMoveData {
     Queenside,
     Kingside,
@@ -37,94 +121,122 @@ pub struct Board {
     pieces: [[Option<Piece>; 8]; 8],
     pub highlight_move: (Square, Square),
     pub highlight_piece: Option<Square>,
+    //white's material less black's, from the pieces set up and kept up to date by each move
     curr_points: i32,
     king_white: Square,
     king_black: Square,
     pub piece_count: u8,
     castle_white: CastleInfo,
     castle_black: CastleInfo, // past_states: Vec<Board> //FOR DEBUG ONLY REMOVE ASAP
+    en_passant: Option<Square>,
+    side_to_move: Color,
+    halfmove_clock: u16,
+    fullmove_number: u16,
+    history: Vec<u64>,
+    hash: u64,
+    //kept in step with pieces for attack and move generation
+    colors: [Bitboard; 2],
+    kinds: [Bitboard; 6],
 }
 
 impl Board {
     pub fn new() -> Board {
-        Self::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR")
+        Self::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1")
+            .expect("start position is valid")
     }
 
-    #[allow(dead_code)]
-    pub fn from(pieces: [[Option<Piece>; 8]; 8], king_white: Square, king_black: Square) -> Board {
-        Board {
-            highlight_move: (Square { x: 16, y: 16 }, Square { x: 16, y: 16 }),
-            highlight_piece: None,
-            curr_points: 0,
-            pieces: pieces,
-            king_white: king_white,
-            king_black: king_black,
-            castle_white: CastleInfo::neither(),
-            castle_black: CastleInfo::neither(),
-            piece_count: pieces.into_iter().flatten().fold(0, |a, b| {
-                a + match b {
-                    Some(_) => 1,
-                    None => 0,
-                }
-            }), // past_states: Vec::new()
+    pub fn from_fen(fen: &str) -> Result<Self, FenError> {
+        let mut builder = BoardBuilder::new();
+        //underscores are accepted in place of spaces, as used in urls
+        let mut fields = fen.split([' ', '_']).filter(|x| !x.is_empty());
+        let ranks: Vec<&str> = fields.next().unwrap_or("").split('/').collect();
+        if ranks.len() != 8 {
+            return Err(FenError::WrongRankCount(ranks.len()));
         }
-    }
-
-    pub fn from_fen(fen: &str) -> Self {
-        let mut pieces = [[None; 8]; 8];
-        let mut piece_count = 0;
-
-        let mut king_white = None;
-        let mut king_black = None;
-        for (index_y_inv, line) in fen.split("/").enumerate() {
+        for (index_y_inv, line) in ranks.into_iter().enumerate() {
             let mut index_x = 0;
             let index_y = 7 - index_y_inv;
+            let too_long = FenError::BadRankLength(index_y as u8 + 1);
             for char in line.chars() {
                 if let Some(skip) = char.to_digit(10) {
+                    //a run of empty squares is 1 to 8 long
+                    if skip == 0 {
+                        return Err(FenError::BadRankLength(index_y as u8 + 1));
+                    }
                     index_x += skip as usize;
-                } else {
-                    pieces[index_y][index_x] = Some(Piece::new(
-                        char.to_ascii_lowercase(),
-                        match char.is_uppercase() {
-                            true => Color::White,
-                            false => Color::Black,
-                        },
-                    ));
-                    piece_count += 1;
-                    if char.to_ascii_lowercase() == 'k' {
-                        match char.is_uppercase() {
-                            true => {
-                                king_white = Some(Square {
-                                    x: index_x as u8,
-                                    y: index_y as u8,
-                                })
-                            }
-                            false => {
-                                king_black = Some(Square {
-                                    x: index_x as u8,
-                                    y: index_y as u8,
-                                })
-                            }
-                        }
+                    if index_x > 8 {
+                        return Err(too_long);
                     }
-                    index_x += 1;
+                    continue;
                 }
+                if index_x >= 8 {
+                    return Err(too_long);
+                }
+                let piece = Piece::from_char(char).ok_or(FenError::UnknownPiece(char))?;
+                let square = Square {
+                    x: index_x as u8,
+                    y: index_y as u8,
+                };
+                builder = builder.piece(square, piece);
+                index_x += 1;
+            }
+            if index_x != 8 {
+                return Err(FenError::BadRankLength(index_y as u8 + 1));
             }
         }
-        Self {
-            highlight_move: (Square { x: 16, y: 16 }, Square { x: 16, y: 16 }),
-            highlight_piece: None,
-            curr_points: 0,
-            pieces: pieces,
-            king_white: king_white.expect("No white king on board!"),
-            king_black: king_black.expect("No black king on board!"),
-            castle_white: CastleInfo::both(),
-            castle_black: CastleInfo::both(),
-            piece_count: piece_count,
+
+        builder = builder.side_to_move(match fields.next() {
+            Some("w") | None => Color::White,
+            Some("b") => Color::Black,
+            Some(side) => return Err(FenError::BadSideToMove(side.to_string())),
+        });
+
+        let castling = fields.next().unwrap_or("-");
+        let repeated = castling
+            .char_indices()
+            .any(|(i, x)| castling[..i].contains(x));
+        if castling != "-" && (repeated || !castling.chars().all(|x| "KQkq".contains(x))) {
+            return Err(FenError::BadCastlingRights(castling.to_string()));
         }
+        builder = builder
+            .castling(
+                Color::White,
+                CastleInfo {
+                    kingside: castling.contains('K'),
+                    queenside: castling.contains('Q'),
+                },
+            )
+            .castling(
+                Color::Black,
+                CastleInfo {
+                    kingside: castling.contains('k'),
+                    queenside: castling.contains('q'),
+                },
+            );
+
+        match fields.next() {
+            Some("-") | None => {}
+            Some(field) => match Square::new(field) {
+                Some(sq) if field.len() == 2 => builder = builder.en_passant(sq),
+                _ => return Err(FenError::BadEnPassant(field.to_string())),
+            },
+        }
+
+        let mut clock = |default: u16| match fields.next() {
+            Some(field) => field
+                .parse()
+                .map_err(|_| FenError::BadClock(field.to_string())),
+            None => Ok(default),
+        };
+        let halfmove_clock = clock(0)?;
+        let fullmove_number = clock(1)?;
+        if let Some(field) = fields.next() {
+            return Err(FenError::ExtraField(field.to_string()));
+        }
+        builder.clocks(halfmove_clock, fullmove_number).build()
     }
 
-    pub fn fen(&self, who_to_move: Color) -> String {
+    pub fn fen(&self) -> String {
         let mut empty: u8 = 0;
         let mut fen: String = String::new();
         for y in (0..8).rev() {
@@ -134,10 +246,7 @@ impl Board {
                         fen += &empty.to_string();
                         empty = 0;
                     }
-                    fen.push(match piece.color {
-                        Color::White => piece.id.to_ascii_uppercase(),
-                        Color::Black => piece.id,
-                    });
+                    fen.push(piece.to_char());
                 } else {
                     empty += 1;
                 }
@@ -150,16 +259,32 @@ impl Board {
                 fen.push('/');
             }
         }
-        fen += "_";
-        fen += match who_to_move {
-            Color::White => "w",
-            Color::Black => "b",
+        fen += match self.side_to_move {
+            Color::White => " w ",
+            Color::Black => " b ",
+        };
+        let castling: String = [
+            (self.castle_white.kingside, 'K'),
+            (self.castle_white.queenside, 'Q'),
+            (self.castle_black.kingside, 'k'),
+            (self.castle_black.queenside, 'q'),
+        ]
+        .iter()
+        .filter(|x| x.0)
+        .map(|x| x.1)
+        .collect();
+        fen += if castling.is_empty() { "-" } else { &castling };
+        fen.push(' ');
+        fen += &match self.en_passant {
+            Some(sq) => sq.to_string(),
+            None => "-".to_string(),
         };
-        fen += "_-_-_0_1";
+        fen += &format!(" {} {}", self.halfmove_clock, self.fullmove_number);
         fen
     }
 
-    pub fn positional_value(id: char, sq: &Square) -> i32 {
+    #[allow(dead_code)]
+    pub fn positional_value(_kind: PieceKind, sq: &Square) -> i32 {
         let mut incr = 3;
         if sq.y == 0 || sq.y == 7 {
             incr -= 3;
@@ -170,15 +295,93 @@ impl Board {
         incr
     }
 
+    //places or clears a square, keeping the bitboards in step
+    fn set(&mut self, x: usize, y: usize, piece: Option<Piece>) {
+        let mask = 1 << (y * 8 + x);
+        if let Some(old) = self.pieces[y][x] {
+            self.colors[bitboard::side(old.color)] &= !mask;
+            self.kinds[old.kind.index()] &= !mask;
+        }
+        if let Some(new) = piece {
+            self.colors[bitboard::side(new.color)] |= mask;
+            self.kinds[new.kind.index()] |= mask;
+        }
+        self.pieces[y][x] = piece;
+    }
+
+    fn sync_bitboards(&mut self) {
+        self.colors = [0; 2];
+        self.kinds = [0; 6];
+        for y in 0..8 {
+            for x in 0..8 {
+                self.set(x, y, self.pieces[y][x]);
+            }
+        }
+    }
+
+    pub fn occupancy(&self) -> Bitboard {
+        self.colors[0] | self.colors[1]
+    }
+
+    pub fn color_bitboard(&self, color: Color) -> Bitboard {
+        self.colors[bitboard::side(color)]
+    }
+
+    pub fn pieces(&self, kind: PieceKind, color: Color) -> Bitboard {
+        self.kinds[kind.index()] & self.color_bitboard(color)
+    }
+
+    //whether any piece of `by` attacks `square`, given the occupancy and with the
+    //pieces in `removed` taken off, so a move can be tried without making it
+    fn attacked_with(&self, sq: usize, by: Color, occupied: Bitboard, removed: Bitboard) -> bool {
+        let them = self.color_bitboard(by) & !removed;
+        let kind = |kind: PieceKind| self.kinds[kind.index()] & them;
+        bitboard::KNIGHT_ATTACKS[sq] & kind(PieceKind::Knight) != 0
+            || bitboard::KING_ATTACKS[sq] & kind(PieceKind::King) != 0
+            || bitboard::PAWN_ATTACKS[bitboard::side(!by)][sq] & kind(PieceKind::Pawn) != 0
+            || bitboard::bishop_attacks(sq, occupied)
+                & (kind(PieceKind::Bishop) | kind(PieceKind::Queen))
+                != 0
+            || bitboard::rook_attacks(sq, occupied)
+                & (kind(PieceKind::Rook) | kind(PieceKind::Queen))
+                != 0
+    }
+
+    pub fn attacked(&self, square: &Square, by: Color) -> bool {
+        self.attacked_with(bitboard::index(square), by, self.occupancy(), 0)
+    }
+
+    //whether `mv` keeps the mover's king out of check, without playing it
+    fn is_legal(&self, mv: &Move, color: Color) -> bool {
+        let (from, to) = (bitboard::bit(&mv.from), bitboard::bit(&mv.to));
+        let moved = self.pieces[mv.from.y as usize][mv.from.x as usize];
+        let mut captured = to;
+        if moved.is_some_and(|piece| piece.kind == PieceKind::Pawn)
+            && mv.from.x != mv.to.x
+            && !self.occupied(&mv.to)
+        {
+            captured = bitboard::bit(&Square {
+                x: mv.to.x,
+                y: mv.from.y,
+            });
+        }
+        let king = match moved.is_some_and(|piece| piece.kind == PieceKind::King) {
+            true => mv.to,
+            false => match color {
+                Color::White => self.king_white,
+                Color::Black => self.king_black,
+            },
+        };
+        let occupied = (self.occupancy() & !from & !captured) | to;
+        !self.attacked_with(bitboard::index(&king), !color, occupied, captured)
+    }
+
     pub fn occupied(&self, square: &Square) -> bool {
-        self.pieces[square.y as usize][square.x as usize].is_some()
+        self.occupancy() & bitboard::bit(square) != 0
     }
 
     pub fn is_color(&self, square: &Square, color: Color) -> bool {
-        match self.piece_at(square) {
-            Some(piece) => piece.color == color,
-            None => false,
-        }
+        self.color_bitboard(color) & bitboard::bit(square) != 0
     }
 
     pub fn square_value(&self, square: &Square) -> i32 {
@@ -196,14 +399,18 @@ impl Board {
         self.pieces[y as usize][x as usize]
     }
 
-    pub fn king_in_check(&self, color: Color) -> bool {
-        let sq = match color {
+    pub fn king_square(&self, color: Color) -> Square {
+        match color {
             Color::White => self.king_white,
             Color::Black => self.king_black,
-        };
+        }
+    }
+
+    pub fn king_in_check(&self, color: Color) -> bool {
+        let sq = self.king_square(color);
         // self.piece_at(&sq).unwrap().in_check(&self, sq)
         match self.piece_at(&sq) {
-            Some(_) => Piece::in_check(&self, sq, color),
+            Some(_) => Piece::in_check(self, sq, color),
             None => {
                 // for state in &self.past_states {
                 //     println!("{}", state);
@@ -214,45 +421,86 @@ impl Board {
         }
     }
 
-    pub fn exec_move(&mut self, from: &Square, to: &Square) -> MoveData {
+    pub fn exec_move(&mut self, mv: &Move) -> UndoInfo {
+        let (from, to) = (&mv.from, &mv.to);
         let tx = to.x as usize;
         let ty = to.y as usize;
         let fx = from.x as usize;
         let fy = from.y as usize;
         let mut castle_data = CastleMoveData::None;
         // self.past_states.push(self.clone());
+        self.history.push(self.hash);
+        let state = BoardState {
+            en_passant: self.en_passant,
+            halfmove_clock: self.halfmove_clock,
+            castle_white: self.castle_white,
+            castle_black: self.castle_black,
+            hash: self.hash,
+        };
+
+        //get piece (move after modifications made)
+        let mut moved = self.pieces[fy][fx].expect("no piece to move!");
+        self.hash ^= zobrist::piece(&moved, from);
+
+        //a pawn moving diagonally onto an empty square takes en passant
+        let en_passant = moved.kind == PieceKind::Pawn && fx != tx && self.pieces[ty][tx].is_none();
+        let taken_y = if en_passant { fy } else { ty };
 
         //get value
-        let (taken, mut points) = match self.pieces[ty][tx] {
+        let (taken, mut points) = match self.pieces[taken_y][tx] {
             Some(taken) => {
                 self.piece_count -= 1;
+                self.hash ^= zobrist::piece(
+                    &taken,
+                    &Square {
+                        x: tx as u8,
+                        y: taken_y as u8,
+                    },
+                );
                 (Some(taken), taken.points)
             }
             None => (None, 0),
         };
+        if en_passant {
+            self.set(tx, fy, None);
+        }
 
-        //get piece (move after modifications made)
-        let mut moved = self.pieces[fy][fx].expect("no piece to move!");
-        moved.has_moved = true;
+        self.halfmove_clock = if moved.kind == PieceKind::Pawn || taken.is_some() {
+            0
+        } else {
+            self.halfmove_clock + 1
+        };
+        if moved.color == Color::Black {
+            self.fullmove_number += 1;
+        }
+        self.side_to_move = !moved.color;
+        self.hash ^= zobrist::SIDE;
+
+        if let Some(sq) = self.en_passant {
+            self.hash ^= zobrist::en_passant(&sq);
+        }
+        self.en_passant = if moved.kind == PieceKind::Pawn && (fy as i8 - ty as i8).abs() == 2 {
+            Some(Square {
+                x: fx as u8,
+                y: ((fy + ty) / 2) as u8,
+            })
+        } else {
+            None
+        };
+        if let Some(sq) = self.en_passant {
+            self.hash ^= zobrist::en_passant(&sq);
+        }
 
         //test for promotion
-        let mut promoted = false;
-        if moved.id == 'p' {
-            if ty
+        let promoted = moved.kind == PieceKind::Pawn
+            && ty
                 == match moved.color {
                     Color::White => 7,
                     Color::Black => 0,
-                }
-            {
-                let piece = Piece::new('q', moved.color);
-                self.pieces[ty][tx] = Some(piece);
-                points += piece.points;
-                promoted = true;
-            }
-        }
+                };
 
         //test for castling or king move or pawn move
-        if moved.id == 'k' {
+        if moved.kind == PieceKind::King {
             match moved.color {
                 Color::White => {
                     self.king_white = *to;
@@ -263,28 +511,41 @@ impl Board {
                     self.castle_black = CastleInfo::neither();
                 }
             }
-            match tx as i8 - fx as i8 {
+            let rook_move = match tx as i8 - fx as i8 {
                 2 => {
                     //kingside
-                    self.pieces[ty][5] = self.pieces[ty][7];
-                    self.pieces[ty][7] = None;
                     castle_data = CastleMoveData::Kingside;
                     points += 3;
+                    Some((7, 5))
                 }
                 -2 => {
                     //queenside
-                    self.pieces[ty][3] = self.pieces[ty][0];
-                    self.pieces[ty][0] = None;
                     castle_data = CastleMoveData::Queenside;
                     points += 2;
+                    Some((0, 3))
                 }
-                _ => {}
+                _ => None,
+            };
+            if let Some((rook_from, rook_to)) = rook_move {
+                if let Some(rook) = self.pieces[ty][rook_from] {
+                    for x in [rook_from, rook_to] {
+                        self.hash ^= zobrist::piece(
+                            &rook,
+                            &Square {
+                                x: x as u8,
+                                y: ty as u8,
+                            },
+                        );
+                    }
+                }
+                self.set(rook_to, ty, self.pieces[ty][rook_from]);
+                self.set(rook_from, ty, None);
             }
-        } else if moved.id == 'p' {
+        } else if moved.kind == PieceKind::Pawn {
             let incr = (fy as i32 - ty as i32).abs();
             moved.points += incr;
             points += incr;
-        } else if moved.id == 'n' {
+        } else if moved.kind == PieceKind::Knight {
             let mut incr = 3;
             if ty == 0 || ty == 7 {
                 incr -= 3;
@@ -295,59 +556,90 @@ impl Board {
             //println!("incr {} old {}", incr, (moved.points - Piece::VALUE_KNIGHT));
             points += incr - (moved.points - Piece::VALUE_KNIGHT);
             moved.points = Piece::VALUE_KNIGHT + incr;
-        } else if moved.id == 'r' {
-            if tx == 0 || tx == 7 {
-                match moved.color {
-                    Color::White => {
-                        if tx == 0 {
-                            self.castle_white.queenside = false;
-                        } else {
-                            self.castle_white.kingside = false;
-                        }
-                    }
-                    Color::Black => {
-                        if tx == 0 {
-                            self.castle_white.queenside = false;
-                        } else {
-                            self.castle_white.kingside = false;
-                        }
-                    }
-                }
-            }
         }
 
-        //move piece
-        self.pieces[ty][tx] = Some(moved);
-        self.pieces[fy][fx] = None;
+        //a rook leaving or being taken on its home square ends castling on that side
+        self.revoke_castling(from);
+        self.revoke_castling(to);
+
+        //move piece, replacing a promoting pawn (moved keeps the pawn for unexec)
+        let placed = if promoted {
+            let piece = Piece::new(mv.promotion.unwrap_or(PieceKind::Queen), moved.color);
+            points += piece.points - moved.points;
+            piece
+        } else {
+            moved
+        };
+        self.set(tx, ty, Some(placed));
+        self.set(fx, fy, None);
+        self.hash ^= zobrist::piece(&placed, to);
+        self.hash ^= zobrist::castling(&state.castle_white, &state.castle_black)
+            ^ zobrist::castling(&self.castle_white, &self.castle_black);
 
         points *= if moved.color == Color::White { 1 } else { -1 };
         self.curr_points += points;
-        (points, moved, taken, promoted, castle_data)
+        UndoInfo {
+            points,
+            moved,
+            taken,
+            castle: castle_data,
+            en_passant,
+            state,
+        }
     }
 
-    pub fn unexec_move(
-        &mut self,
-        from: &Square,
-        to: &Square,
-        (points, moved, taken, promoted, castle_data): MoveData,
-    ) {
+    fn revoke_castling(&mut self, square: &Square) {
+        let rights = match square.y {
+            0 => &mut self.castle_white,
+            7 => &mut self.castle_black,
+            _ => return,
+        };
+        match square.x {
+            0 => rights.queenside = false,
+            7 => rights.kingside = false,
+            _ => {}
+        }
+    }
+
+    pub fn unexec_move(&mut self, mv: &Move, undo: UndoInfo) {
+        let UndoInfo {
+            points,
+            moved,
+            taken,
+            castle,
+            en_passant,
+            state,
+            ..
+        } = undo;
+        let (from, to) = (&mv.from, &mv.to);
         let fx = from.x as usize;
         let fy = from.y as usize;
         if taken.is_some() {
             self.piece_count += 1;
         }
-        if promoted {
-            self.pieces[fy][fx] = Some(Piece::new('p', moved.color))
+        self.set(fx, fy, Some(moved));
+        if en_passant {
+            self.set(to.x as usize, to.y as usize, None);
+            self.set(to.x as usize, fy, taken);
         } else {
-            self.pieces[fy][fx] = Some(moved);
+            self.set(to.x as usize, to.y as usize, taken);
         }
-        self.pieces[to.y as usize][to.x as usize] = taken;
-        if moved.id == 'k' {
+        self.en_passant = state.en_passant;
+        self.halfmove_clock = state.halfmove_clock;
+        self.castle_white = state.castle_white;
+        self.castle_black = state.castle_black;
+        self.hash = state.hash;
+        if moved.color == Color::Black {
+            self.fullmove_number -= 1;
+        }
+        self.side_to_move = moved.color;
+        self.history.pop();
+        if moved.kind == PieceKind::King {
             match moved.color {
                 Color::White => self.king_white = *from,
                 Color::Black => self.king_black = *from,
             }
-        } else if moved.id == 'n' {
+        } else if moved.kind == PieceKind::Knight {
             let mut incr = 3;
             if fy == 0 || fy == 7 {
                 incr -= 3;
@@ -358,18 +650,18 @@ impl Board {
             //println!("incr {} old {}", incr, (moved.points - Piece::VALUE_KNIGHT));
             //points += incr - (moved.points - Piece::VALUE_KNIGHT);
             self.pieces[fy][fx].as_mut().unwrap().points = Piece::VALUE_KNIGHT + incr;
-        } else if moved.id == 'p' {
+        } else if moved.kind == PieceKind::Pawn {
             self.pieces[fy][fx].as_mut().unwrap().points -= (fy as i32 - to.y as i32).abs()
         }
         //no need to influence points here - they are stored in points var
-        match castle_data {
+        match castle {
             CastleMoveData::Kingside => {
-                self.pieces[fy][7] = self.pieces[to.y as usize][5];
-                self.pieces[fy][5] = None;
+                self.set(7, fy, self.pieces[to.y as usize][5]);
+                self.set(5, fy, None);
             }
             CastleMoveData::Queenside => {
-                self.pieces[fy][0] = self.pieces[to.y as usize][3];
-                self.pieces[fy][3] = None;
+                self.set(0, fy, self.pieces[to.y as usize][3]);
+                self.set(3, fy, None);
             }
             CastleMoveData::None => {}
         }
@@ -377,36 +669,16 @@ impl Board {
     }
 
     pub fn get_pieces(&self, color: Color) -> Vec<(Square, Piece)> {
-        let mut result = Vec::with_capacity(16);
-        for y in 0..8 {
-            for x in 0..8 {
-                if let Some(piece) = self.pieces[y][x] {
-                    if piece.color == color {
-                        result.push((
-                            Square {
-                                x: x as u8,
-                                y: y as u8,
-                            },
-                            piece,
-                        ));
-                    }
-                }
-            }
-        }
-        result
+        bitboard::squares(self.color_bitboard(color))
+            .map(|sq| (sq, self.pieces[sq.y as usize][sq.x as usize].unwrap()))
+            .collect()
     }
 
-    pub fn filter_checks(&self, moves: &mut Vec<(Square, Square)>, color: Color) {
-        let mut board_clone = self.clone();
-        moves.retain(move |(from, to)| {
-            let data = board_clone.exec_move(&from, &to);
-            let result = !board_clone.king_in_check(color);
-            board_clone.unexec_move(&from, &to, data);
-            result
-        });
+    pub fn filter_checks(&self, moves: &mut Vec<Move>, color: Color) {
+        moves.retain(|mv| self.is_legal(mv, color));
     }
 
-    pub fn get_moves(&self, color: Color) -> Vec<(Square, Square)> {
+    pub fn get_moves(&self, color: Color) -> Vec<Move> {
         let mut moves = Vec::new();
         for (pos, piece) in self.get_pieces(color) {
             piece.get_moves(self, pos, &mut moves);
@@ -415,6 +687,53 @@ impl Board {
         moves
     }
 
+    //number of leaf positions reachable in exactly `depth` moves
+    pub fn perft(&mut self, depth: u8) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+        let moves = self.get_moves(self.side_to_move);
+        if depth == 1 {
+            return moves.len() as u64;
+        }
+        let mut nodes = 0;
+        for mv in moves {
+            let data = self.exec_move(&mv);
+            nodes += self.perft(depth - 1);
+            self.unexec_move(&mv, data);
+        }
+        nodes
+    }
+
+    //perft split by the first move, for finding which move generation goes wrong
+    pub fn divide(&mut self, depth: u8) -> Vec<(Move, u64)> {
+        let mut result = Vec::new();
+        for mv in self.get_moves(self.side_to_move) {
+            let data = self.exec_move(&mv);
+            result.push((mv, self.perft(depth.saturating_sub(1))));
+            self.unexec_move(&mv, data);
+        }
+        result
+    }
+
+    pub fn en_passant(&self) -> Option<Square> {
+        self.en_passant
+    }
+
+    pub fn side_to_move(&self) -> Color {
+        self.side_to_move
+    }
+
+    #[allow(dead_code)]
+    pub fn halfmove_clock(&self) -> u16 {
+        self.halfmove_clock
+    }
+
+    #[allow(dead_code)]
+    pub fn fullmove_number(&self) -> u16 {
+        self.fullmove_number
+    }
+
     pub fn can_castle(&self, color: Color) -> &CastleInfo {
         match color {
             Color::White => &self.castle_white,
@@ -422,32 +741,130 @@ impl Board {
         }
     }
 
+    //endgame tables only hold positions where neither side can castle
+    pub fn has_castling_rights(&self) -> bool {
+        [&self.castle_white, &self.castle_black]
+            .iter()
+            .any(|rights| rights.kingside || rights.queenside)
+    }
+
+    #[allow(dead_code)]
     pub fn is_in_checkmate(&self, color: Color) -> bool {
         if !self.king_in_check(color) {
             return false;
         }
-        self.get_moves(color).len() == 0
+        self.get_moves(color).is_empty()
     }
 
-    pub fn eval(&self, color: Color) -> f32 {
-        if self.is_in_checkmate(!color) {
-            CHECKMATE
-        } else if self.is_in_checkmate(color) {
-            -CHECKMATE
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    //full zobrist hash of the position, exec_move keeps self.hash equal to this incrementally
+    pub fn compute_hash(&self) -> u64 {
+        let mut hash = zobrist::castling(&self.castle_white, &self.castle_black);
+        for y in 0..8 {
+            for x in 0..8 {
+                if let Some(piece) = self.piece_at_xy(x, y) {
+                    hash ^= zobrist::piece(&piece, &Square { x, y });
+                }
+            }
+        }
+        if self.side_to_move == Color::Black {
+            hash ^= zobrist::SIDE;
+        }
+        if let Some(sq) = self.en_passant {
+            hash ^= zobrist::en_passant(&sq);
+        }
+        hash
+    }
+
+    //times the current position has occurred, counting this one
+    pub fn repetitions(&self) -> usize {
+        let key = self.hash;
+        //positions before the last capture or pawn move can't recur
+        1 + self
+            .history
+            .iter()
+            .rev()
+            .take(self.halfmove_clock as usize)
+            .skip(1)
+            .step_by(2)
+            .filter(|x| **x == key)
+            .count()
+    }
+
+    pub fn is_threefold_repetition(&self) -> bool {
+        self.repetitions() >= 3
+    }
+
+    pub fn is_fifty_move_draw(&self) -> bool {
+        self.halfmove_clock >= 100
+    }
+
+    //neither side can mate: bare kings, a lone minor piece, or bishops all on one colour
+    pub fn is_insufficient_material(&self) -> bool {
+        let mut knights = 0;
+        let mut bishop_squares = [false; 2];
+        for (y, row) in self.pieces.iter().enumerate() {
+            for (x, piece) in row.iter().enumerate() {
+                match piece.map(|x| x.kind) {
+                    Some(PieceKind::Knight) => knights += 1,
+                    Some(PieceKind::Bishop) => bishop_squares[(x + y) % 2] = true,
+                    Some(PieceKind::King) | None => {}
+                    Some(_) => return false,
+                }
+            }
+        }
+        match (knights, bishop_squares) {
+            (0, [true, true]) => false,
+            (0, _) => true,
+            (1, [false, false]) => true,
+            _ => false,
+        }
+    }
+
+    pub fn outcome(&self) -> Outcome {
+        let color = self.side_to_move;
+        if self.get_moves(color).is_empty() {
+            if self.king_in_check(color) {
+                Outcome::Checkmate(!color)
+            } else {
+                Outcome::Stalemate
+            }
+        } else if self.is_fifty_move_draw() {
+            Outcome::Draw(DrawReason::FiftyMoves)
+        } else if self.is_threefold_repetition() {
+            Outcome::Draw(DrawReason::Repetition)
+        } else if self.is_insufficient_material() {
+            Outcome::Draw(DrawReason::InsufficientMaterial)
         } else {
-            let points: f32 = match color {
-                Color::White => self.curr_points,
-                Color::Black => -self.curr_points,
-            } as f32;
-            points
+            Outcome::Ongoing
         }
     }
 
+    pub fn eval(&self, color: Color) -> f32 {
+        match self.outcome() {
+            Outcome::Checkmate(winner) if winner == color => CHECKMATE,
+            Outcome::Checkmate(_) => -CHECKMATE,
+            Outcome::Stalemate | Outcome::Draw(_) => DRAW,
+            Outcome::Ongoing => self.material(color),
+        }
+    }
+
+    pub fn material(&self, color: Color) -> f32 {
+        let points: f32 = match color {
+            Color::White => self.curr_points,
+            Color::Black => -self.curr_points,
+        } as f32;
+        points
+    }
+
     pub fn display(&self, perspective: Color) {
         let mut moves = Vec::new();
         if let Some(piece_square) = self.highlight_piece {
             if let Some(piece) = self.piece_at(&piece_square) {
-                piece.get_moves(&self, piece_square, &mut moves);
+                piece.get_moves(self, piece_square, &mut moves);
             }
         }
         let mut forward_y = 0usize..8;
@@ -470,7 +887,7 @@ impl Board {
                 {
                     "\x1b[1;48;5;11;38;5;0m"
                 } else if moves.iter().any(|v| {
-                    v.1 == Square {
+                    v.to == Square {
                         x: x as u8,
                         y: y as u8,
                     }
@@ -483,23 +900,15 @@ impl Board {
                     }
                 };
                 if let Some(piece) = self.pieces[y][x] {
-                    print!(
-                        "{} {} \x1b[0m",
-                        highlight_color,
-                        if piece.color == Color::White {
-                            piece.id.to_ascii_uppercase()
-                        } else {
-                            piece.id
-                        }
-                    );
+                    print!("{} {} \x1b[0m", highlight_color, piece.to_char());
                 } else {
                     print!("{}   \x1b[0m", highlight_color);
                 }
             }
-            print!(" {}\n", y + 1);
+            println!(" {}", y + 1);
         }
-        print!(
-            " {}\n",
+        println!(
+            " {}",
             match perspective {
                 Color::White => "a  b  c  d  e  f  g  h",
                 Color::Black => "h  g  f  e  d  c  b  a",
@@ -514,11 +923,11 @@ impl fmt::Display for Board {
         let mut moves = Vec::new();
         if let Some(piece_square) = self.highlight_piece {
             if let Some(piece) = self.piece_at(&piece_square) {
-                piece.get_moves(&self, piece_square, &mut moves);
+                piece.get_moves(self, piece_square, &mut moves);
             }
         }
         for y in (0..8).rev() {
-            write!(f, "+---+---+---+---+---+---+---+---+\n")?;
+            writeln!(f, "+---+---+---+---+---+---+---+---+")?;
             for x in 0..8 {
                 write!(f, "|")?;
                 let highlight_color = if (self.highlight_move.0.x == x as u8
@@ -527,7 +936,7 @@ impl fmt::Display for Board {
                 {
                     "\x1b[1;103m"
                 } else if moves.iter().any(|v| {
-                    v.1 == Square {
+                    v.to == Square {
                         x: x as u8,
                         y: y as u8,
                     }
@@ -537,21 +946,12 @@ impl fmt::Display for Board {
                     ""
                 };
                 if let Some(piece) = self.pieces[y][x] {
-                    write!(
-                        f,
-                        "{} {} \x1b[0m",
-                        highlight_color,
-                        if piece.color == Color::White {
-                            piece.id.to_ascii_uppercase()
-                        } else {
-                            piece.id
-                        }
-                    )?;
+                    write!(f, "{} {} \x1b[0m", highlight_color, piece.to_char())?;
                 } else {
                     write!(f, "{}   \x1b[0m", highlight_color)?;
                 }
             }
-            write!(f, "| {}\n", y + 1)?;
+            writeln!(f, "| {}", y + 1)?;
         }
         write!(
             f,
@@ -560,3 +960,168 @@ impl fmt::Display for Board {
         Ok(())
     }
 }
+
+//sets up a position square by square, checking it could occur in a game
+#[derive(Debug, Clone)]
+pub struct BoardBuilder {
+    pieces: [[Option<Piece>; 8]; 8],
+    side_to_move: Color,
+    castle_white: CastleInfo,
+    castle_black: CastleInfo,
+    en_passant: Option<Square>,
+    halfmove_clock: u16,
+    fullmove_number: u16,
+}
+
+impl BoardBuilder {
+    //an empty board, white to move without castling rights
+    pub fn new() -> BoardBuilder {
+        BoardBuilder {
+            pieces: [[None; 8]; 8],
+            side_to_move: Color::White,
+            castle_white: CastleInfo::neither(),
+            castle_black: CastleInfo::neither(),
+            en_passant: None,
+            halfmove_clock: 0,
+            fullmove_number: 1,
+        }
+    }
+
+    //replaces whatever stood on the square
+    pub fn piece(mut self, square: Square, piece: Piece) -> BoardBuilder {
+        self.pieces[square.y as usize][square.x as usize] = Some(piece);
+        self
+    }
+
+    pub fn side_to_move(mut self, color: Color) -> BoardBuilder {
+        self.side_to_move = color;
+        self
+    }
+
+    pub fn castling(mut self, color: Color, rights: CastleInfo) -> BoardBuilder {
+        match color {
+            Color::White => self.castle_white = rights,
+            Color::Black => self.castle_black = rights,
+        }
+        self
+    }
+
+    pub fn en_passant(mut self, square: Square) -> BoardBuilder {
+        self.en_passant = Some(square);
+        self
+    }
+
+    pub fn clocks(mut self, halfmove_clock: u16, fullmove_number: u16) -> BoardBuilder {
+        self.halfmove_clock = halfmove_clock;
+        self.fullmove_number = fullmove_number;
+        self
+    }
+
+    fn is_piece(&self, square: Square, kind: PieceKind, color: Color) -> bool {
+        matches!(
+            self.pieces[square.y as usize][square.x as usize],
+            Some(piece) if piece.kind == kind && piece.color == color
+        )
+    }
+
+    //checks the position is legal, counting the material of the pieces placed
+    //rather than assuming an even start
+    pub fn build(self) -> Result<Board, FenError> {
+        let mut king_white = None;
+        let mut king_black = None;
+        let mut piece_count = 0;
+        let mut curr_points = 0;
+        for (square, piece) in (0..64).filter_map(|i| {
+            let square = Square { x: i % 8, y: i / 8 };
+            self.pieces[square.y as usize][square.x as usize].map(|piece| (square, piece))
+        }) {
+            if piece.kind == PieceKind::Pawn && (square.y == 0 || square.y == 7) {
+                return Err(FenError::PawnOnBackRank(square));
+            }
+            if piece.kind == PieceKind::King {
+                let king = match piece.color {
+                    Color::White => &mut king_white,
+                    Color::Black => &mut king_black,
+                };
+                if king.is_some() {
+                    return Err(FenError::MultipleKings(piece.color));
+                }
+                *king = Some(square);
+            }
+            //material balance, so positions that aren't level evaluate as such
+            curr_points += match piece.color {
+                Color::White => piece.points,
+                Color::Black => -piece.points,
+            };
+            piece_count += 1;
+        }
+        let king_white = king_white.ok_or(FenError::MissingKing(Color::White))?;
+        let king_black = king_black.ok_or(FenError::MissingKing(Color::Black))?;
+
+        //each right needs the king and that rook still on their starting squares
+        for (right, allowed, color, rook_x) in [
+            ('K', self.castle_white.kingside, Color::White, 7),
+            ('Q', self.castle_white.queenside, Color::White, 0),
+            ('k', self.castle_black.kingside, Color::Black, 7),
+            ('q', self.castle_black.queenside, Color::Black, 0),
+        ] {
+            let y = match color {
+                Color::White => 0,
+                Color::Black => 7,
+            };
+            if allowed
+                && !(self.is_piece(Square { x: 4, y }, PieceKind::King, color)
+                    && self.is_piece(Square { x: rook_x, y }, PieceKind::Rook, color))
+            {
+                return Err(FenError::InconsistentCastlingRights(right));
+            }
+        }
+
+        if let Some(sq) = self.en_passant {
+            //the square behind a pawn of the side that just moved
+            let (rank, pawn_rank) = match self.side_to_move {
+                Color::White => (5, 4),
+                Color::Black => (2, 3),
+            };
+            if sq.y != rank
+                || self.pieces[sq.y as usize][sq.x as usize].is_some()
+                || !self.is_piece(
+                    Square {
+                        x: sq.x,
+                        y: pawn_rank,
+                    },
+                    PieceKind::Pawn,
+                    !self.side_to_move,
+                )
+            {
+                return Err(FenError::BadEnPassant(sq.to_string()));
+            }
+        }
+
+        let mut board = Board {
+            highlight_move: (Square { x: 16, y: 16 }, Square { x: 16, y: 16 }),
+            highlight_piece: None,
+            curr_points,
+            pieces: self.pieces,
+            king_white,
+            king_black,
+            castle_white: self.castle_white,
+            castle_black: self.castle_black,
+            en_passant: self.en_passant,
+            side_to_move: self.side_to_move,
+            halfmove_clock: self.halfmove_clock,
+            fullmove_number: self.fullmove_number,
+            history: Vec::new(),
+            hash: 0,
+            colors: [0; 2],
+            kinds: [0; 6],
+            piece_count,
+        };
+        board.sync_bitboards();
+        board.hash = board.compute_hash();
+        if board.king_in_check(!self.side_to_move) {
+            return Err(FenError::OpponentInCheck);
+        }
+        Ok(board)
+    }
+}
@@ -1,107 +1,415 @@
 use crate::chengine::*;
-use reqwest;
-use serde::Deserialize;
-use serde_json;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SearchLimit {
+    Depth(u8),
+    MoveTime(Duration),
+    Clock {
+        remaining: Duration,
+        increment: Duration,
+        moves_to_go: Option<u32>,
+    },
+    Nodes(u64),
+    Infinite,
+}
+
+impl SearchLimit {
+    //deepest iteration the search is allowed to start
+    fn max_depth(&self) -> u8 {
+        match self {
+            SearchLimit::Depth(depth) => *depth,
+            _ => Computer::MAX_DEPTH,
+        }
+    }
+
+    //how long this move may take, leaving some slack on the clock
+    fn time_budget(&self) -> Option<Duration> {
+        match *self {
+            SearchLimit::MoveTime(time) => Some(time),
+            SearchLimit::Clock {
+                remaining,
+                increment,
+                moves_to_go,
+            } => {
+                let share = remaining / moves_to_go.unwrap_or(30).max(1) + increment * 3 / 4;
+                Some(share.min(remaining.saturating_sub(Duration::from_millis(50)) / 2))
+            }
+            _ => None,
+        }
+    }
+}
+
+//reported after every finished iteration
+#[derive(Debug, Clone)]
+pub struct SearchInfo {
+    pub depth: u8,
+    pub score: f32,
+    pub nodes: u64,
+    pub elapsed: Duration,
+    pub pv: Vec<Move>,
+}
 
 pub struct Computer {
     pub following_opening: bool,
     seek_opening: usize,
     curr_opening: &'static Opening,
-    color: Color,
-}
-
-#[derive(Deserialize, Debug)]
-struct EndgameResponse {
-    moves: Vec<EndgameMove>,
-}
-#[derive(Deserialize, Debug)]
-struct EndgameMove {
-    uci: String,
+    table: TranspositionTable,
+    nodes: u64,
+    node_limit: Option<u64>,
+    deadline: Option<Instant>,
+    stopped: bool,
+    //set when a score below the current node came from a repetition or the
+    //fifty move rule, which depend on how the position was reached
+    path_draw: bool,
+    completed_depth: u8,
+    stop: Arc<AtomicBool>,
+    tablebase: Box<dyn TablebaseProvider>,
+    //the local tables, set aside while an online tablebase is asked instead
+    local_tablebase: Option<Box<dyn TablebaseProvider>>,
+    //positions with more pieces than this are never looked up
+    pub tablebase_pieces: u8,
 }
 
 impl Computer {
-    pub fn new(color: Color, opening: &'static Opening) -> Computer {
+    pub const DEFAULT_TABLE_MB: usize = 16;
+    pub const MAX_DEPTH: u8 = 64;
+    pub const MAX_TABLEBASE_PIECES: u8 = 7;
+
+    pub fn new(opening: &'static Opening) -> Computer {
         Computer {
             following_opening: true,
             seek_opening: 0,
             curr_opening: opening,
-            color: color,
+            table: TranspositionTable::new(Self::DEFAULT_TABLE_MB),
+            nodes: 0,
+            node_limit: None,
+            deadline: None,
+            stopped: false,
+            path_draw: false,
+            completed_depth: 0,
+            stop: Arc::new(AtomicBool::new(false)),
+            tablebase: Box::new(NoTablebase),
+            local_tablebase: None,
+            tablebase_pieces: Self::MAX_TABLEBASE_PIECES,
         }
     }
 
-    pub fn probe_tablebase(&self, board: &Board) -> Result<(Square, Square), &str> {
-        let req = "http://tablebase.lichess.ovh/standard?fen=".to_string() + &board.fen(self.color);
-        println!("{:?}", req);
-        let res_text = match reqwest::blocking::get(req) {
-            Ok(r) => match r.text() {
-                Ok(r2) => r2,
-                Err(..) => return Err("Couldn't get text from response"),
-            },
-            Err(..) => return Err("GET request failed"),
-        };
-        match serde_json::from_str::<EndgameResponse>(&res_text) {
-            Ok(endgame_res) => Ok((
-                Square::new(&endgame_res.moves[0].uci[0..2]).unwrap(),
-                Square::new(&endgame_res.moves[0].uci[2..4]).unwrap(),
-            )),
-            Err(..) => {
-                println!("JSON parse error: response '{}'", res_text);
-                Err("JSON parse error")
+    //setting the flag from another thread ends the current search early
+    pub fn stop_handle(&self) -> Arc<AtomicBool> {
+        self.stop.clone()
+    }
+
+    //nodes visited by the last search
+    pub fn nodes(&self) -> u64 {
+        self.nodes
+    }
+
+    //depth of the last iteration the last search finished
+    pub fn completed_depth(&self) -> u8 {
+        self.completed_depth
+    }
+
+    //counts the node and reports whether the search has run out of time or nodes
+    fn out_of_budget(&mut self) -> bool {
+        self.nodes += 1;
+        //depth 1 always finishes so there is a move to fall back on
+        if self.completed_depth == 0 {
+            return false;
+        }
+        if self.node_limit.is_some_and(|limit| self.nodes >= limit)
+            || (self.nodes & 1023 == 0
+                && (self.stop.load(Ordering::Relaxed)
+                    || self.deadline.is_some_and(|x| Instant::now() >= x)))
+        {
+            self.stopped = true;
+        }
+        self.stopped
+    }
+
+    //replaces the local tables, which stay behind an online tablebase while it is on
+    pub fn set_tablebase(&mut self, tablebase: Box<dyn TablebaseProvider>) {
+        match &mut self.local_tablebase {
+            Some(local) => *local = tablebase,
+            None => self.tablebase = tablebase,
+        }
+    }
+
+    //asks the server instead of the local tables until it is turned off with None
+    pub fn set_online_tablebase(&mut self, online: Option<Box<dyn TablebaseProvider>>) {
+        match online {
+            Some(online) => {
+                let replaced = std::mem::replace(&mut self.tablebase, online);
+                self.local_tablebase.get_or_insert(replaced);
+            }
+            None => {
+                if let Some(local) = self.local_tablebase.take() {
+                    self.tablebase = local;
+                }
             }
         }
     }
 
-    fn quiescence(board: &mut Board, curr_color: Color, mut alpha: f32, beta: f32) -> f32 {
-        let stand_pat = board.eval(curr_color);
+    //loads syzygy tables from the directories in `path`, returning how many were found
+    pub fn set_syzygy_path(&mut self, path: &str) -> usize {
+        let tablebase = syzygy::LocalTablebase::open(path);
+        let count = tablebase.len();
+        self.set_tablebase(match count {
+            0 => Box::new(NoTablebase),
+            _ => Box::new(tablebase),
+        });
+        count
+    }
+
+    //shorter wins and longer losses score better, by however many plies are known
+    fn wdl_score(wdl: Wdl, distance: Option<i32>) -> f32 {
+        let distance = distance.map_or(0.0, |x| x.abs() as f32);
+        match wdl {
+            Wdl::Win => TABLEBASE_WIN - distance,
+            Wdl::Loss => -TABLEBASE_WIN + distance,
+            //the fifty move rule turns the rest into draws
+            _ => DRAW,
+        }
+    }
+
+    //tables are probed straight after captures and pawn moves, when the halfmove
+    //clock is back at 0 and the fifty move rule cannot interfere
+    fn tablebase_score(&mut self, board: &mut Board) -> Option<f32> {
+        if board.halfmove_clock() != 0 || board.piece_count > self.tablebase_pieces {
+            return None;
+        }
+        let wdl = self.tablebase.probe_wdl(board).ok()?;
+        Some(Self::wdl_score(wdl, None))
+    }
+
+    //resizing drops everything stored so far
+    pub fn set_table_size(&mut self, megabytes: usize) {
+        self.table = TranspositionTable::new(megabytes);
+    }
+
+    //forgets everything searched so far, keeping the table's size
+    pub fn clear_table(&mut self) {
+        self.table.clear();
+    }
+
+    //score for the side to move when a draw rule applies
+    fn draw_score(&mut self, board: &Board) -> Option<f32> {
+        if board.is_insufficient_material() {
+            return Some(DRAW);
+        }
+        //a repeat inside the search can be repeated again, so treat it as a draw already
+        if board.is_fifty_move_draw() || board.repetitions() >= 2 {
+            self.path_draw = true;
+            return Some(DRAW);
+        }
+        None
+    }
+
+    //score for the side to move when it has no legal moves, `ply` from the root
+    fn no_moves_score(board: &Board, curr_color: Color, ply: u8) -> f32 {
+        match board.king_in_check(curr_color) {
+            true => -CHECKMATE + ply as f32,
+            false => DRAW,
+        }
+    }
+
+    //the table keeps mates counted from the position stored rather than the root
+    fn score_to_table(score: f32, ply: u8) -> f32 {
+        if score >= MATE_BOUND {
+            score + ply as f32
+        } else if score <= -MATE_BOUND {
+            score - ply as f32
+        } else {
+            score
+        }
+    }
+
+    fn score_from_table(score: f32, ply: u8) -> f32 {
+        if score >= MATE_BOUND {
+            score - ply as f32
+        } else if score <= -MATE_BOUND {
+            score + ply as f32
+        } else {
+            score
+        }
+    }
+
+    //a stored score that is good enough to return without searching
+    fn table_cutoff(entry: &TableEntry, depth: u8, ply: u8, alpha: f32, beta: f32) -> Option<f32> {
+        if entry.depth < depth {
+            return None;
+        }
+        let score = Self::score_from_table(entry.score, ply);
+        match entry.bound {
+            Bound::Exact => Some(score),
+            Bound::Lower if score >= beta => Some(score),
+            Bound::Upper if score <= alpha => Some(score),
+            _ => None,
+        }
+    }
+
+    fn store(
+        &mut self,
+        board: &Board,
+        depth: u8,
+        ply: u8,
+        score: f32,
+        bounds: (f32, f32),
+        best: Option<Move>,
+    ) {
+        self.table.store(TableEntry {
+            key: board.hash(),
+            depth,
+            bound: if score <= bounds.0 {
+                Bound::Upper
+            } else if score >= bounds.1 {
+                Bound::Lower
+            } else {
+                Bound::Exact
+            },
+            score: Self::score_to_table(score, ply),
+            best,
+        });
+    }
+
+    fn quiescence(
+        &mut self,
+        board: &mut Board,
+        curr_color: Color,
+        mut alpha: f32,
+        beta: f32,
+        ply: u8,
+    ) -> f32 {
+        if self.out_of_budget() {
+            return 0.0;
+        }
+        if let Some(score) = self.draw_score(board) {
+            return score;
+        }
+        let hash_move = match self.table.probe(board.hash()) {
+            Some(entry) => {
+                if let Some(score) = Self::table_cutoff(entry, 0, ply, alpha, beta) {
+                    return score;
+                }
+                entry.best
+            }
+            None => None,
+        };
+        let mut moves = board.get_moves(curr_color);
+        if moves.is_empty() {
+            return Self::no_moves_score(board, curr_color, ply);
+        }
+        let alpha_orig = alpha;
+        let stand_pat = board.material(curr_color);
         if stand_pat >= beta {
             return beta;
         }
         if alpha < stand_pat {
             alpha = stand_pat;
         }
-        for (from, to) in board.get_moves(curr_color) {
-            if !board.occupied(&to) {
+        let mut best = None;
+        Self::order_moves(board, &mut moves, hash_move);
+        for mv in moves {
+            if !mv.is_capture() && mv.promotion.is_none() {
                 //should be faster than retain, maybe bench this?
                 continue;
             }
-            let move_data = board.exec_move(&from, &to);
-            let score = -Self::quiescence(board, !curr_color, -beta, -alpha);
-            board.unexec_move(&from, &to, move_data);
+            let undo = board.exec_move(&mv);
+            let score = -self.quiescence(board, !curr_color, -beta, -alpha, ply + 1);
+            board.unexec_move(&mv, undo);
+            //an unfinished search must not be stored
+            if self.stopped {
+                return 0.0;
+            }
 
             if score >= beta {
+                self.store(board, 0, ply, beta, (alpha_orig, beta), Some(mv));
                 return beta;
             }
             if score > alpha {
                 alpha = score;
+                best = Some(mv);
             }
         }
-        return alpha;
+        self.store(board, 0, ply, alpha, (alpha_orig, beta), best);
+        alpha
     }
 
-    fn move_sort(board: &Board, a: &(Square, Square), b: &(Square, Square)) -> std::cmp::Ordering {
-        board
-            .square_value(&b.1)
-            .partial_cmp(&board.square_value(&a.1))
-            .unwrap()
+    fn move_value(board: &Board, mv: &Move) -> i32 {
+        board.square_value(&mv.to)
+            + match mv.promotion {
+                Some(kind) => kind.value(),
+                None => 0,
+            }
+    }
+
+    fn move_sort(board: &Board, a: &Move, b: &Move) -> std::cmp::Ordering {
+        Self::move_value(board, b).cmp(&Self::move_value(board, a))
+    }
+
+    //best move from the table first, then by what is captured or promoted to
+    fn order_moves(board: &Board, moves: &mut [Move], hash_move: Option<Move>) {
+        moves.sort_by(|a, b| Self::move_sort(board, a, b));
+        if let Some(index) = moves.iter().position(|mv| Some(*mv) == hash_move) {
+            moves[..=index].rotate_right(1);
+        }
     }
 
-    fn negamax(board: &mut Board, curr_color: Color, mut alpha: f32, beta: f32, depth: u8) -> f32 {
+    fn negamax(
+        &mut self,
+        board: &mut Board,
+        curr_color: Color,
+        mut alpha: f32,
+        beta: f32,
+        depth: u8,
+        ply: u8,
+    ) -> f32 {
         if depth == 0 {
-            return Self::quiescence(board, curr_color, alpha, beta);
+            return self.quiescence(board, curr_color, alpha, beta, ply);
         }
+        if self.out_of_budget() {
+            return 0.0;
+        }
+        if let Some(score) = self.draw_score(board) {
+            return score;
+        }
+        if let Some(score) = self.tablebase_score(board) {
+            return score;
+        }
+        let hash_move = match self.table.probe(board.hash()) {
+            Some(entry) => {
+                if let Some(score) = Self::table_cutoff(entry, depth, ply, alpha, beta) {
+                    return score;
+                }
+                entry.best
+            }
+            None => None,
+        };
+        let alpha_orig = alpha;
         let mut best = f32::NEG_INFINITY; // +1 to avoid overflow on negate
+        let mut best_move = None;
         let mut moves = board.get_moves(curr_color);
-        moves.sort_by(|a, b| Self::move_sort(board, a, b));
-        for (from, to) in moves {
-            let move_data = board.exec_move(&from, &to);
-            let score = -Self::negamax(board, !curr_color, -beta, -alpha, depth - 1);
+        if moves.is_empty() {
+            return Self::no_moves_score(board, curr_color, ply);
+        }
+        let outer_path_draw = std::mem::take(&mut self.path_draw);
+        Self::order_moves(board, &mut moves, hash_move);
+        for mv in moves {
+            let undo = board.exec_move(&mv);
+            let score = -self.negamax(board, !curr_color, -beta, -alpha, depth - 1, ply + 1);
 
-            if score > best {
+            if score > best || best_move.is_none() {
                 best = score;
+                best_move = Some(mv);
             }
 
             //this has to come before the break as the board is shared state
-            board.unexec_move(&from, &to, move_data);
+            board.unexec_move(&mv, undo);
+            if self.stopped {
+                return 0.0;
+            }
             if best > alpha {
                 alpha = best;
                 if alpha >= beta {
@@ -109,27 +417,37 @@ impl Computer {
                 }
             }
         }
-        return best;
+        if !self.path_draw {
+            self.store(board, depth, ply, best, (alpha_orig, beta), best_move);
+        }
+        self.path_draw |= outer_path_draw;
+        best
     }
 
     fn negamax_with_move(
+        &mut self,
         board: &Board,
         curr_color: Color,
         mut alpha: f32,
         beta: f32,
         depth: u8,
-    ) -> (f32, Option<(Square, Square)>) {
+    ) -> (f32, Option<Move>) {
+        let alpha_orig = alpha;
+        let mut board = board.clone();
         let mut best = (f32::NEG_INFINITY, None);
         let mut moves = board.get_moves(curr_color);
-        moves.sort_by(|a, b| Self::move_sort(board, a, b));
-        for (from, to) in moves {
-            let mut board_copy = board.clone();
-            board_copy.exec_move(&from, &to);
-            let score = -Self::negamax(&mut board_copy, !curr_color, -beta, -alpha, depth - 1);
-            //score = -score;
-            //board.unexec_move(&from, &to, move_data);
-            if score > best.0 {
-                best = (score, Some((from, to)));
+        let hash_move = self.table.probe(board.hash()).and_then(|entry| entry.best);
+        Self::order_moves(&board, &mut moves, hash_move);
+        self.path_draw = false;
+        for mv in moves {
+            let undo = board.exec_move(&mv);
+            let score = -self.negamax(&mut board, !curr_color, -beta, -alpha, depth - 1, 1);
+            board.unexec_move(&mv, undo);
+            if self.stopped {
+                return (f32::NEG_INFINITY, None);
+            }
+            if score > best.0 || best.1.is_none() {
+                best = (score, Some(mv));
             }
 
             if best.0 > alpha {
@@ -139,23 +457,23 @@ impl Computer {
                 }
             }
         }
-        return best;
+        if !self.path_draw {
+            self.store(&board, depth, 0, best.0, (alpha_orig, beta), best.1);
+        }
+        best
     }
 
-    pub fn get_next_from_opening(
-        &mut self,
-        maybe_last_move: &Option<(Square, Square)>,
-    ) -> Option<(Square, Square)> {
+    pub fn get_next_from_opening(&mut self, maybe_last_move: &Option<Move>) -> Option<Move> {
         if self.seek_opening == self.curr_opening.moves.len() {
             match maybe_last_move {
                 None => {
                     //my go
-                    if self.curr_opening.next.len() == 0 {
-                        return None;
+                    if self.curr_opening.next.is_empty() {
+                        None
                     } else {
                         self.curr_opening = &self.curr_opening.next[0];
                         self.seek_opening = 0;
-                        return self.get_next_from_opening(&None);
+                        self.get_next_from_opening(&None)
                     }
                 }
                 Some(last_move) => {
@@ -170,22 +488,22 @@ impl Computer {
                     }
                     if found_opening {
                         self.seek_opening = 1; //skip opponent move, NOTE = 1 NOT += 1
-                        return self.get_next_from_opening(&None);
+                        self.get_next_from_opening(&None)
                     } else {
-                        return None;
+                        None
                     }
                 }
             }
         } else {
             match maybe_last_move {
-                None => return Some(self.curr_opening.moves[self.seek_opening]), //my go
+                None => Some(self.curr_opening.moves[self.seek_opening]), //my go
                 Some(last_move) => {
                     //their go
                     if self.curr_opening.moves[self.seek_opening] == *last_move {
                         self.seek_opening += 1;
-                        return self.get_next_from_opening(&None);
+                        self.get_next_from_opening(&None)
                     } else {
-                        return None;
+                        None
                     }
                 }
             }
@@ -195,30 +513,122 @@ impl Computer {
     pub fn get_move(
         &mut self,
         board: &Board,
-        maybe_last_move: &Option<(Square, Square)>,
-        depth: u8,
-    ) -> (f32, (Square, Square)) {
+        maybe_last_move: &Option<Move>,
+        limit: SearchLimit,
+    ) -> Option<(f32, Move)> {
+        self.nodes = 0;
+        self.completed_depth = 0;
         if self.following_opening {
             if let Some(opening_move) = self.get_next_from_opening(maybe_last_move) {
                 self.seek_opening += 1;
-                return (f32::INFINITY, opening_move);
+                return Some((f32::INFINITY, opening_move));
             } else {
                 self.following_opening = false;
             }
         }
-        if board.piece_count <= 7 {
-            return (
-                f32::INFINITY,
-                self.probe_tablebase(board).expect("Communication error"),
-            );
+        self.search(board, limit)
+    }
+
+    //the tablebase move, as long as it really is legal here
+    fn probe_root(&mut self, board: &Board) -> Option<ProbeResult> {
+        if board.piece_count > self.tablebase_pieces {
+            return None;
+        }
+        let result = self.tablebase.probe(board).ok()?;
+        let mv = board
+            .get_moves(board.side_to_move())
+            .into_iter()
+            .find(|x| *x == result.mv)?;
+        Some(ProbeResult { mv, ..result })
+    }
+
+    //iterative deepening, keeping the result of the deepest iteration that finished.
+    //None when the side to move has no legal moves
+    pub fn search(&mut self, board: &Board, limit: SearchLimit) -> Option<(f32, Move)> {
+        self.search_with_info(board, limit, |_| {})
+    }
+
+    pub fn search_with_info(
+        &mut self,
+        board: &Board,
+        limit: SearchLimit,
+        mut on_iteration: impl FnMut(&SearchInfo),
+    ) -> Option<(f32, Move)> {
+        let start = Instant::now();
+        self.nodes = 0;
+        self.stopped = false;
+        self.completed_depth = 0;
+        self.deadline = limit.time_budget().map(|budget| start + budget);
+        self.node_limit = match limit {
+            SearchLimit::Nodes(nodes) => Some(nodes),
+            _ => None,
+        };
+
+        //a position in the tables needs no search at all, anything going wrong
+        //with the lookup just means searching as usual
+        if let Some(ProbeResult { wdl, mv, dtz, dtm }) = self.probe_root(board) {
+            let score = Self::wdl_score(wdl, dtm.or(dtz));
+            self.completed_depth = 1;
+            on_iteration(&SearchInfo {
+                depth: 1,
+                score,
+                nodes: 0,
+                elapsed: start.elapsed(),
+                pv: vec![mv],
+            });
+            return Some((score, mv));
+        }
+
+        let color = board.side_to_move();
+        if board.get_moves(color).is_empty() {
+            return None;
+        }
+        let mut result = None;
+        for depth in 1..=limit.max_depth().max(1) {
+            match self.negamax_with_move(board, color, f32::NEG_INFINITY, f32::INFINITY, depth) {
+                (score, Some(mv)) if !self.stopped => {
+                    result = Some((score, mv));
+                    self.completed_depth = depth;
+                    on_iteration(&SearchInfo {
+                        depth,
+                        score,
+                        nodes: self.nodes,
+                        elapsed: start.elapsed(),
+                        pv: self.principal_variation(board, depth),
+                    });
+                    //a forced mate will not get any better
+                    if score.abs() >= MATE_BOUND {
+                        break;
+                    }
+                }
+                _ => break,
+            }
+            if self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+            {
+                break;
+            }
         }
-        match Self::negamax_with_move(board, self.color, f32::NEG_INFINITY, f32::INFINITY, depth) {
-            (a, Some(b)) => {
-                //let b0 = b[0];
-                //println!("{} {:?}", a, b.into_iter().map(|x| (x.0.disp(), x.1.disp())).collect::<Vec<_>>());
-                (a, b)
+        result
+    }
+
+    //follows the best moves stored in the table from the root
+    fn principal_variation(&self, board: &Board, depth: u8) -> Vec<Move> {
+        let mut board = board.clone();
+        let mut pv = Vec::new();
+        let mut seen = Vec::new();
+        while pv.len() < depth as usize && !seen.contains(&board.hash()) {
+            seen.push(board.hash());
+            let Some(mv) = self.table.probe(board.hash()).and_then(|entry| entry.best) else {
+                break;
+            };
+            if !board.get_moves(board.side_to_move()).contains(&mv) {
+                break;
             }
-            _ => panic!(),
+            board.exec_move(&mv);
+            pv.push(mv);
         }
+        pv
     }
 }
@@ -1 +1,8 @@
-pub const CHECKMATE: f32 = f32::INFINITY; //avoid default move crash
+//being mated is -CHECKMATE, and the search scores a mate `n` plies from the
+//root as CHECKMATE - n
+pub const CHECKMATE: f32 = 1_000_000.0;
+//anything past this is a forced mate, however far away
+pub const MATE_BOUND: f32 = CHECKMATE - 1000.0;
+pub const DRAW: f32 = 0.0;
+//a tablebase win, above any material balance but short of a mate the search has seen
+pub const TABLEBASE_WIN: f32 = 50000.0;
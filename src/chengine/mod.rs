@@ -1,13 +1,25 @@
+pub mod bitboard;
 pub mod board;
 pub mod color;
 pub mod computer;
 pub mod constant;
+pub mod moves;
 pub mod opening;
+pub mod pgn;
 pub mod piece;
+pub mod retrograde;
+pub mod san;
 pub mod square;
+pub mod syzygy;
+pub mod tablebase;
 #[cfg(test)]
 pub mod tests;
+pub mod transposition;
+pub mod uci;
+pub mod xboard;
+pub mod zobrist;
 
 pub use crate::chengine::{
-    board::*, color::*, computer::*, constant::*, opening::*, piece::*, square::*,
+    bitboard::Bitboard, board::*, color::*, computer::*, constant::*, moves::*, opening::*,
+    piece::*, square::*, tablebase::*, transposition::*,
 };
@@ -0,0 +1,117 @@
+use crate::chengine::*;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+#[derive(Debug, Copy, Clone)]
+pub struct Move {
+    pub from: Square,
+    pub to: Square,
+    pub promotion: Option<PieceKind>,
+    //set by move generation, moves typed in or read from a book have none
+    pub flags: u8,
+}
+
+impl Move {
+    pub const PROMOTIONS: [PieceKind; 4] = [
+        PieceKind::Queen,
+        PieceKind::Rook,
+        PieceKind::Bishop,
+        PieceKind::Knight,
+    ];
+
+    pub const CAPTURE: u8 = 1;
+    pub const CASTLE: u8 = 2;
+    pub const EN_PASSANT: u8 = 4;
+    pub const DOUBLE_PUSH: u8 = 8;
+
+    pub fn new(from: Square, to: Square) -> Move {
+        Move {
+            from,
+            to,
+            promotion: None,
+            flags: 0,
+        }
+    }
+
+    pub fn promote(from: Square, to: Square, kind: PieceKind) -> Move {
+        Move {
+            from,
+            to,
+            promotion: Some(kind),
+            flags: 0,
+        }
+    }
+
+    pub fn with_flags(mut self, flags: u8) -> Move {
+        self.flags |= flags;
+        self
+    }
+
+    pub fn is_capture(&self) -> bool {
+        self.flags & Self::CAPTURE != 0
+    }
+
+    #[allow(dead_code)]
+    pub fn is_castle(&self) -> bool {
+        self.flags & Self::CASTLE != 0
+    }
+
+    #[allow(dead_code)]
+    pub fn is_en_passant(&self) -> bool {
+        self.flags & Self::EN_PASSANT != 0
+    }
+
+    #[allow(dead_code)]
+    pub fn is_double_push(&self) -> bool {
+        self.flags & Self::DOUBLE_PUSH != 0
+    }
+
+    //long algebraic notation as used by UCI, e.g. e2e4 or e7e8q
+    pub fn from_uci(text: &str) -> Option<Move> {
+        let from = Square::new(text.get(0..2)?)?;
+        let to = Square::new(text.get(2..4)?)?;
+        let promotion = match text.get(4..)? {
+            "" => return Some(Move::new(from, to)),
+            "n" => PieceKind::Knight,
+            "b" => PieceKind::Bishop,
+            "r" => PieceKind::Rook,
+            "q" => PieceKind::Queen,
+            _ => return None,
+        };
+        Some(Move::promote(from, to, promotion))
+    }
+
+    pub fn to_uci(self) -> String {
+        match self.promotion {
+            Some(kind) => format!("{}{}{}", self.from, self.to, kind.to_char()),
+            None => format!("{}{}", self.from, self.to),
+        }
+    }
+}
+
+//flags follow from the position, so they play no part in which move it is
+impl PartialEq for Move {
+    fn eq(&self, other: &Self) -> bool {
+        self.from == other.from && self.to == other.to && self.promotion == other.promotion
+    }
+}
+
+impl Eq for Move {}
+
+impl Hash for Move {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.from.hash(state);
+        self.to.hash(state);
+        self.promotion.hash(state);
+    }
+}
+
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} to {}", self.from, self.to)?;
+        if let Some(kind) = self.promotion {
+            write!(f, " ({})", kind.to_char())?;
+        }
+        Ok(())
+    }
+}
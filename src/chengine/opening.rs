@@ -3,7 +3,7 @@ use lazy_static::lazy_static;
 
 #[derive(Clone)]
 pub struct Opening {
-    pub moves: Vec<(Square, Square)>,
+    pub moves: Vec<Move>,
     pub next: Vec<Opening>,
 }
 
@@ -11,7 +11,7 @@ macro_rules! opening {
     ( $($from: expr => $to: expr),+; $next: expr) => {
         Opening {
             moves: vec![
-                $( (Square::new($from).unwrap(), Square::new($to).unwrap()), )+
+                $( Move::new(Square::new($from).unwrap(), Square::new($to).unwrap()), )+
             ],
             next: $next
         }
@@ -0,0 +1,332 @@
+use crate::chengine::*;
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+
+//the seven tag roster every exported game starts with, and what each holds when unknown
+const ROSTER: [(&str, &str); 7] = [
+    ("Event", "?"),
+    ("Site", "?"),
+    ("Date", "????.??.??"),
+    ("Round", "?"),
+    ("White", "?"),
+    ("Black", "?"),
+    ("Result", "*"),
+];
+const LINE_LENGTH: usize = 79;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PgnError {
+    BadTag(String),
+    BadFen(FenError),
+    IllegalMove(usize, String),
+    Unterminated(char),
+}
+
+impl fmt::Display for PgnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PgnError::BadTag(tag) => write!(f, "malformed tag pair '{}'", tag),
+            PgnError::BadFen(err) => write!(f, "invalid FEN tag: {}", err),
+            PgnError::IllegalMove(ply, san) => {
+                write!(f, "illegal move '{}' at ply {}", san, ply + 1)
+            }
+            PgnError::Unterminated(open) => write!(f, "'{}' is never closed", open),
+        }
+    }
+}
+
+impl std::error::Error for PgnError {}
+
+impl From<FenError> for PgnError {
+    fn from(err: FenError) -> Self {
+        PgnError::BadFen(err)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Game {
+    pub tags: Vec<(String, String)>,
+    pub moves: Vec<Move>,
+    //keyed by how many moves were played before the comment
+    pub comments: Vec<(usize, String)>,
+    pub result: String,
+}
+
+impl Game {
+    pub fn new() -> Game {
+        Game {
+            tags: ROSTER
+                .iter()
+                .map(|(name, unknown)| (name.to_string(), unknown.to_string()))
+                .collect(),
+            moves: Vec::new(),
+            comments: Vec::new(),
+            result: "*".to_string(),
+        }
+    }
+
+    pub fn result_of(outcome: Outcome) -> &'static str {
+        match outcome {
+            Outcome::Ongoing => "*",
+            Outcome::Checkmate(Color::White) => "1-0",
+            Outcome::Checkmate(Color::Black) => "0-1",
+            Outcome::Stalemate | Outcome::Draw(_) => "1/2-1/2",
+        }
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(tag, _)| tag == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn set_tag(&mut self, name: &str, value: &str) {
+        match self.tags.iter_mut().find(|(tag, _)| tag == name) {
+            Some(tag) => tag.1 = value.to_string(),
+            None => self.tags.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    pub fn set_result(&mut self, result: &str) {
+        self.result = result.to_string();
+        self.set_tag("Result", result);
+    }
+
+    //the position before the first move, from the FEN tag if there is one
+    pub fn start(&self) -> Result<Board, FenError> {
+        match self.tag("FEN") {
+            Some(fen) => Board::from_fen(fen),
+            None => Ok(Board::new()),
+        }
+    }
+
+    //the position after the last move
+    #[allow(dead_code)]
+    pub fn board(&self) -> Result<Board, PgnError> {
+        let mut board = self.start()?;
+        for mv in &self.moves {
+            board.exec_move(mv);
+        }
+        Ok(board)
+    }
+
+    pub fn to_pgn(&self) -> Result<String, PgnError> {
+        let mut pgn = String::new();
+        for (name, value) in &self.tags {
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+            pgn += &format!("[{} \"{}\"]\n", name, value);
+        }
+        pgn.push('\n');
+
+        let mut board = self.start()?;
+        let mut tokens = Vec::new();
+        let mut after_comment = false;
+        for ply in 0..=self.moves.len() {
+            for (_, comment) in self.comments.iter().filter(|(at, _)| *at == ply) {
+                tokens.push(format!("{{{}}}", comment));
+                after_comment = true;
+            }
+            let Some(mv) = self.moves.get(ply) else {
+                break;
+            };
+            let color = board.side_to_move();
+            if !board.get_moves(color).contains(mv) {
+                return Err(PgnError::IllegalMove(ply, mv.to_uci()));
+            }
+            match color {
+                Color::White => tokens.push(format!("{}.", board.fullmove_number())),
+                Color::Black if ply == 0 || after_comment => {
+                    tokens.push(format!("{}...", board.fullmove_number()))
+                }
+                Color::Black => {}
+            }
+            tokens.push(san::to_san(&board, mv));
+            board.exec_move(mv);
+            after_comment = false;
+        }
+        tokens.push(self.result.clone());
+
+        let mut line = String::new();
+        for token in tokens {
+            if !line.is_empty() && line.len() + 1 + token.len() > LINE_LENGTH {
+                pgn += &line;
+                pgn.push('\n');
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line += &token;
+        }
+        pgn += &line;
+        pgn.push('\n');
+        Ok(pgn)
+    }
+
+    //every game in the text, variations and annotation glyphs are skipped
+    pub fn read(text: &str) -> Result<Vec<Game>, PgnError> {
+        let mut games = Vec::new();
+        let mut chars = text.chars().peekable();
+        //None until the first tag or move of a game is seen
+        let mut current: Option<(Game, Board)> = None;
+        let mut at_line_start = true;
+        while let Some(c) = chars.next() {
+            let line_start = at_line_start;
+            at_line_start = c == '\n';
+            match c {
+                c if c.is_whitespace() => {}
+                //escape mechanism, the whole line is ignored
+                '%' if line_start => Self::skip_line(&mut chars),
+                '[' => {
+                    let (mut game, mut board) = match current.take() {
+                        //tags after moves start the next game
+                        Some((game, board)) if game.moves.is_empty() => (game, board),
+                        Some((game, _)) => {
+                            games.push(game);
+                            Self::empty()
+                        }
+                        None => Self::empty(),
+                    };
+                    let (name, value) = Self::read_tag(&mut chars)?;
+                    if name == "FEN" {
+                        board = Board::from_fen(&value)?;
+                    }
+                    if name == "Result" {
+                        game.result = value.clone();
+                    }
+                    game.set_tag(&name, &value);
+                    current = Some((game, board));
+                }
+                '{' => {
+                    let mut comment = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => comment.push(c),
+                            None => return Err(PgnError::Unterminated('{')),
+                        }
+                    }
+                    let (game, _) = current.get_or_insert_with(Self::empty);
+                    game.comments
+                        .push((game.moves.len(), comment.trim().to_string()));
+                }
+                ';' => {
+                    let comment: String = chars.by_ref().take_while(|&c| c != '\n').collect();
+                    at_line_start = true;
+                    let (game, _) = current.get_or_insert_with(Self::empty);
+                    game.comments
+                        .push((game.moves.len(), comment.trim().to_string()));
+                }
+                '(' => Self::skip_variation(&mut chars)?,
+                _ => {
+                    let mut token = c.to_string();
+                    while let Some(&next) = chars.peek() {
+                        if next.is_whitespace() || "{}();[]".contains(next) {
+                            break;
+                        }
+                        token.push(next);
+                        chars.next();
+                    }
+                    let (game, board) = current.get_or_insert_with(Self::empty);
+                    match token.as_str() {
+                        "1-0" | "0-1" | "1/2-1/2" | "*" => {
+                            game.set_result(&token);
+                            games.push(current.take().unwrap().0);
+                            continue;
+                        }
+                        _ if token.starts_with('$') => continue,
+                        _ => {}
+                    }
+                    //move numbers can be glued to the move, as in 1.e4 or 12...Nf6
+                    //but only digits followed by a dot are a number, 0-0 is castling
+                    let digits = token.trim_start_matches(|c: char| c.is_ascii_digit());
+                    let san = match digits.starts_with('.') {
+                        true => digits.trim_start_matches('.'),
+                        false => token.as_str(),
+                    };
+                    if san.is_empty() {
+                        continue;
+                    }
+                    let mv = san::parse_san(board, san)
+                        .ok_or_else(|| PgnError::IllegalMove(game.moves.len(), san.to_string()))?;
+                    board.exec_move(&mv);
+                    game.moves.push(mv);
+                }
+            }
+        }
+        //a game cut off without a result is still worth keeping
+        if let Some((game, _)) = current {
+            if !game.moves.is_empty() || !game.tags.is_empty() {
+                games.push(game);
+            }
+        }
+        Ok(games)
+    }
+
+    fn empty() -> (Game, Board) {
+        let mut game = Game::new();
+        game.tags.clear();
+        (game, Board::new())
+    }
+
+    fn skip_line(chars: &mut Peekable<Chars>) {
+        for c in chars.by_ref() {
+            if c == '\n' {
+                break;
+            }
+        }
+    }
+
+    //`[Name "value"]` with the opening bracket already read
+    fn read_tag(chars: &mut Peekable<Chars>) -> Result<(String, String), PgnError> {
+        let mut raw = String::new();
+        let mut in_string = false;
+        loop {
+            match chars.next() {
+                Some('\\') if in_string => match chars.next() {
+                    Some(c) => raw.push(c),
+                    None => return Err(PgnError::Unterminated('[')),
+                },
+                Some('"') => {
+                    in_string = !in_string;
+                    raw.push('"');
+                }
+                Some(']') if !in_string => break,
+                Some(c) => raw.push(c),
+                None => return Err(PgnError::Unterminated('[')),
+            }
+        }
+        let (name, value) = raw
+            .trim()
+            .split_once(char::is_whitespace)
+            .ok_or_else(|| PgnError::BadTag(raw.clone()))?;
+        let value = value
+            .trim()
+            .strip_prefix('"')
+            .and_then(|x| x.strip_suffix('"'))
+            .ok_or_else(|| PgnError::BadTag(raw.clone()))?;
+        Ok((name.to_string(), value.to_string()))
+    }
+
+    //recursive annotation variations, with the opening parenthesis already read
+    fn skip_variation(chars: &mut Peekable<Chars>) -> Result<(), PgnError> {
+        let mut depth = 1;
+        while depth > 0 {
+            match chars.next() {
+                Some('(') => depth += 1,
+                Some(')') => depth -= 1,
+                //parentheses inside comments do not count
+                Some('{') => {
+                    if !chars.by_ref().any(|c| c == '}') {
+                        return Err(PgnError::Unterminated('{'));
+                    }
+                }
+                Some(_) => {}
+                None => return Err(PgnError::Unterminated('(')),
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,10 +1,90 @@
 use crate::chengine::*;
 
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub enum PieceKind {
+    Pawn,
+    Knight,
+    Bishop,
+    Rook,
+    Queen,
+    King,
+}
+
+impl PieceKind {
+    pub const ALL: [PieceKind; 6] = [
+        PieceKind::Pawn,
+        PieceKind::Knight,
+        PieceKind::Bishop,
+        PieceKind::Rook,
+        PieceKind::Queen,
+        PieceKind::King,
+    ];
+
+    //FEN letter in either case
+    pub fn from_char(letter: char) -> Option<PieceKind> {
+        match letter.to_ascii_lowercase() {
+            'p' => Some(PieceKind::Pawn),
+            'n' => Some(PieceKind::Knight),
+            'b' => Some(PieceKind::Bishop),
+            'r' => Some(PieceKind::Rook),
+            'q' => Some(PieceKind::Queen),
+            'k' => Some(PieceKind::King),
+            _ => None,
+        }
+    }
+
+    //lowercase FEN letter
+    pub fn to_char(self) -> char {
+        match self {
+            PieceKind::Pawn => 'p',
+            PieceKind::Knight => 'n',
+            PieceKind::Bishop => 'b',
+            PieceKind::Rook => 'r',
+            PieceKind::Queen => 'q',
+            PieceKind::King => 'k',
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn glyph(self, color: Color) -> char {
+        let glyphs = match color {
+            Color::White => ['♙', '♘', '♗', '♖', '♕', '♔'],
+            Color::Black => ['♟', '♞', '♝', '♜', '♛', '♚'],
+        };
+        glyphs[self.index()]
+    }
+
+    #[allow(dead_code)]
+    pub fn from_glyph(glyph: char) -> Option<(PieceKind, Color)> {
+        [Color::White, Color::Black].into_iter().find_map(|color| {
+            PieceKind::ALL
+                .into_iter()
+                .find(|kind| kind.glyph(color) == glyph)
+                .map(|kind| (kind, color))
+        })
+    }
+
+    //position in PieceKind::ALL, also the bitboard and zobrist order
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    pub fn value(self) -> i32 {
+        match self {
+            PieceKind::King => Piece::VALUE_KING,
+            PieceKind::Queen => Piece::VALUE_QUEEN,
+            PieceKind::Rook => Piece::VALUE_ROOK,
+            PieceKind::Bishop => Piece::VALUE_BISHOP,
+            PieceKind::Knight => Piece::VALUE_KNIGHT,
+            PieceKind::Pawn => Piece::VALUE_PAWN,
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 pub struct Piece {
-    pub id: char,
+    pub kind: PieceKind,
     pub points: i32,
-    pub has_moved: bool,
     pub color: Color,
 }
 
@@ -16,264 +96,119 @@ impl Piece {
     pub const VALUE_KNIGHT: i32 = 300;
     pub const VALUE_PAWN: i32 = 100;
 
-    pub fn new(id: char, color: Color) -> Piece {
+    pub fn new(kind: PieceKind, color: Color) -> Piece {
         Piece {
-            id: id,
-            color: color,
-            has_moved: false,
-            points: match id {
-                'k' => Self::VALUE_KING,
-                'q' => Self::VALUE_QUEEN,
-                'r' => Self::VALUE_ROOK,
-                'b' => Self::VALUE_BISHOP,
-                'n' => Self::VALUE_KNIGHT,
-                'p' => Self::VALUE_PAWN,
-                _ => panic!("Invalid piece '{}'", id),
-            },
+            kind,
+            color,
+            points: kind.value(),
+        }
+    }
+
+    //FEN letter, uppercase for white
+    pub fn from_char(letter: char) -> Option<Piece> {
+        let color = match letter.is_ascii_uppercase() {
+            true => Color::White,
+            false => Color::Black,
+        };
+        Some(Piece::new(PieceKind::from_char(letter)?, color))
+    }
+
+    pub fn to_char(self) -> char {
+        match self.color {
+            Color::White => self.kind.to_char().to_ascii_uppercase(),
+            Color::Black => self.kind.to_char(),
         }
     }
 
-    pub fn get_moves(&self, board: &Board, from: Square, moves: &mut Vec<(Square, Square)>) {
+    pub fn get_moves(&self, board: &Board, from: Square, moves: &mut Vec<Move>) {
         let pawn_dir = if self.color == Color::White { 1 } else { -1 };
-        if self.id == 'p' {
+        if self.kind == PieceKind::Pawn {
+            let start_rank = if self.color == Color::White { 1 } else { 6 };
+            let push = |moves: &mut Vec<Move>, dest: Square, flags: u8| {
+                if dest.y == 0 || dest.y == 7 {
+                    for kind in Move::PROMOTIONS {
+                        moves.push(Move::promote(from, dest, kind).with_flags(flags));
+                    }
+                } else {
+                    moves.push(Move::new(from, dest).with_flags(flags));
+                }
+            };
             if let Some(dest) = from + (0, pawn_dir) {
                 if !board.occupied(&dest) {
                     if let Some(dest_double) = dest + (0, pawn_dir) {
-                        if !self.has_moved && !board.occupied(&dest_double) {
-                            moves.push((from, dest_double));
+                        if from.y == start_rank && !board.occupied(&dest_double) {
+                            moves.push(Move::new(from, dest_double).with_flags(Move::DOUBLE_PUSH));
                         }
                     }
-                    moves.push((from, dest));
-                }
-            }
-            if let Some(dest) = from + (1, pawn_dir) {
-                if board.is_color(&dest, !self.color) {
-                    moves.push((from, dest));
-                }
-            }
-            if let Some(dest) = from + (-1, pawn_dir) {
-                if board.is_color(&dest, !self.color) {
-                    moves.push((from, dest));
-                }
-            }
-        } else if self.id == 'n' {
-            for square in [
-                (1, 2),
-                (-1, 2),
-                (1, -2),
-                (-1, -2),
-                (2, 1),
-                (-2, 1),
-                (2, -1),
-                (-2, -1),
-            ] {
-                if let Some(dest) = from + square {
-                    if !board.is_color(&dest, self.color) {
-                        moves.push((from, dest));
-                    }
+                    push(moves, dest, 0);
                 }
             }
-        } else if self.id == 'b' {
-            for signs in [(1i8, 1i8), (1, -1), (-1, -1), (-1, 1)] {
-                for xy in 1i8.. {
-                    if let Some(dest) = from + (xy * signs.0, xy * signs.1) {
-                        if !board.occupied(&dest) {
-                            moves.push((from, dest));
-                        } else {
-                            if board.is_color(&dest, !self.color) {
-                                moves.push((from, dest))
-                            }
-                            break;
-                        }
-                    } else {
-                        break;
+            for side in [1, -1] {
+                if let Some(dest) = from + (side, pawn_dir) {
+                    if board.is_color(&dest, !self.color) {
+                        push(moves, dest, Move::CAPTURE);
+                    } else if board.en_passant() == Some(dest)
+                        && board.is_color(
+                            &Square {
+                                x: dest.x,
+                                y: from.y,
+                            },
+                            !self.color,
+                        )
+                    {
+                        push(moves, dest, Move::CAPTURE | Move::EN_PASSANT);
                     }
                 }
             }
-        } else if self.id == 'r' {
-            for signs in [(1i8, 0i8), (-1, 0), (0, -1), (0, 1)] {
-                for xy in 1i8.. {
-                    if let Some(dest) = from + (xy * signs.0, xy * signs.1) {
-                        if !board.occupied(&dest) {
-                            moves.push((from, dest));
-                        } else {
-                            if board.is_color(&dest, !self.color) {
-                                moves.push((from, dest))
-                            }
-                            break;
-                        }
-                    } else {
-                        break;
-                    }
-                }
+        } else {
+            let sq = bitboard::index(&from);
+            let occupied = board.occupancy();
+            let attacks = match self.kind {
+                PieceKind::Knight => bitboard::KNIGHT_ATTACKS[sq],
+                PieceKind::Bishop => bitboard::bishop_attacks(sq, occupied),
+                PieceKind::Rook => bitboard::rook_attacks(sq, occupied),
+                PieceKind::Queen => {
+                    bitboard::bishop_attacks(sq, occupied) | bitboard::rook_attacks(sq, occupied)
+                }
+                PieceKind::King => bitboard::KING_ATTACKS[sq],
+                PieceKind::Pawn => unreachable!(),
+            };
+            let enemies = board.color_bitboard(!self.color);
+            for dest in bitboard::squares(attacks & !board.color_bitboard(self.color)) {
+                let flags = match bitboard::bit(&dest) & enemies != 0 {
+                    true => Move::CAPTURE,
+                    false => 0,
+                };
+                moves.push(Move::new(from, dest).with_flags(flags));
             }
-        } else if self.id == 'q' {
-            for signs in [
-                (1i8, 0i8),
-                (-1, 0),
-                (0, -1),
-                (0, 1),
-                (1, 1),
-                (1, -1),
-                (-1, -1),
-                (-1, 1),
-            ] {
-                for xy in 1i8.. {
-                    if let Some(dest) = from + (xy * signs.0, xy * signs.1) {
-                        if !board.occupied(&dest) {
-                            moves.push((from, dest));
-                        } else {
-                            if board.is_color(&dest, !self.color) {
-                                moves.push((from, dest))
-                            }
-                            break;
-                        }
-                    } else {
-                        break;
-                    }
-                }
-            }
-        } else if self.id == 'k' {
-            for dir in [
-                (1i8, 0i8),
-                (-1, 0),
-                (0, -1),
-                (0, 1),
-                (1, 1),
-                (1, -1),
-                (-1, -1),
-                (-1, 1),
+        }
+        if self.kind == PieceKind::King && !Piece::in_check(board, from, self.color) {
+            let castle_info = board.can_castle(self.color);
+            for (allowed, dir, between) in [
+                (castle_info.kingside, 1i8, 2i8),
+                (castle_info.queenside, -1, 3),
             ] {
-                if let Some(dest) = from + dir {
-                    if !board.is_color(&dest, self.color) {
-                        moves.push((from, dest));
-                    }
-                }
-            }
-
-            if !Piece::in_check(board, from, self.color) {
-                let castle_info = board.can_castle(self.color);
-                if castle_info.kingside {
-                    let mut can_castle = true;
-                    let dest = (from + (-2, 0)).unwrap();
-                    for sq in [(from + (-1, 0)).unwrap(), dest] {
-                        if board.occupied(&sq) || Piece::in_check(board, sq, self.color) {
-                            can_castle = false;
-                            break;
-                        }
-                    }
-                    if can_castle {
-                        moves.push((from, dest));
-                    }
+                if !allowed {
+                    continue;
                 }
-                if castle_info.queenside {
-                    let mut can_castle = true;
-                    let dest = (from + (2, 0)).unwrap();
-                    for sq in [(from + (1, 0)).unwrap(), dest] {
-                        if board.occupied(&sq) || Piece::in_check(board, sq, self.color) {
-                            can_castle = false;
-                            break;
-                        }
-                    }
-                    if can_castle {
-                        moves.push((from, dest));
+                //everything up to the rook must be empty, the king's path also unattacked
+                let can_castle = (1..=between).all(|i| match from + (i * dir, 0) {
+                    Some(sq) => {
+                        !board.occupied(&sq) && (i > 2 || !Piece::in_check(board, sq, self.color))
                     }
+                    None => false,
+                });
+                if can_castle {
+                    moves.push(
+                        Move::new(from, (from + (2 * dir, 0)).unwrap()).with_flags(Move::CASTLE),
+                    );
                 }
             }
-
-            // if !self.has_moved {
-            //     let rank = match self.color {
-            //         Color::White => 1,
-            //         Color::Black => 8
-            //     };
-            //     let rook_a = board.piece_at(&Square { x: 0, y: rank });
-            //     let rook_h = board.piece_at(&Square { x: 7, y: rank });
-            //     if rook_h. { //if it hasn't moved it must be our own, so we don't need to check that
-            //         for file in [5, 6] {
-
-            //         }
-            //     }
-            // }
         }
     }
 
+    //whether `square` is attacked by the opponent of `color`
     pub fn in_check(board: &Board, square: Square, color: Color) -> bool {
-        //assumes it is a king for efficiency
-        for signs in &[
-            (1i8, 0i8, 'r'),
-            (-1, 0, 'r'),
-            (0, -1, 'r'),
-            (0, 1, 'r'),
-            (1, 1, 'b'),
-            (1, -1, 'b'),
-            (-1, -1, 'b'),
-            (-1, 1, 'b'),
-        ] {
-            for xy in 1i8.. {
-                if let Some(dest) = square + (xy * signs.0, xy * signs.1) {
-                    if let Some(piece) = board.piece_at(&dest) {
-                        if (piece.id == 'q' || piece.id == signs.2) && piece.color == !color {
-                            return true;
-                        } else if piece.id == 'k' && xy == 1 && piece.color == !color {
-                            return true;
-                        } else {
-                            break;
-                        }
-                    }
-                } else {
-                    break;
-                }
-            }
-        }
-        for &jump in &[
-            (1, 2),
-            (-1, 2),
-            (1, -2),
-            (-1, -2),
-            (2, 1),
-            (-2, 1),
-            (2, -1),
-            (-2, -1),
-        ] {
-            if let Some(dest) = square + jump {
-                if let Some(piece) = board.piece_at(&dest) {
-                    if piece.id == 'n' && piece.color != color {
-                        return true;
-                    }
-                }
-            }
-        }
-        if color == Color::Black {
-            if let Some(dest) = square + (-1, -1) {
-                if let Some(piece) = board.piece_at(&dest) {
-                    if piece.id == 'p' && piece.color == Color::White {
-                        return true;
-                    }
-                }
-            }
-            if let Some(dest) = square + (1, -1) {
-                if let Some(piece) = board.piece_at(&dest) {
-                    if piece.id == 'p' && piece.color == Color::White {
-                        return true;
-                    }
-                }
-            }
-        } else if color == Color::White {
-            if let Some(dest) = square + (-1, 1) {
-                if let Some(piece) = board.piece_at(&dest) {
-                    if piece.id == 'p' && piece.color == Color::Black {
-                        return true;
-                    }
-                }
-            }
-            if let Some(dest) = square + (1, 1) {
-                if let Some(piece) = board.piece_at(&dest) {
-                    if piece.id == 'p' && piece.color == Color::Black {
-                        return true;
-                    }
-                }
-            }
-        }
-        return false;
+        board.attacked(&square, !color)
     }
 }
@@ -0,0 +1,555 @@
+//perfect play tables for a few small endings, worked out backwards from every mate
+//by retrograde analysis instead of being read from syzygy files or the internet
+use crate::chengine::*;
+use std::io::Write;
+use std::path::Path;
+
+const MAGIC: [u8; 4] = *b"CHTB";
+const VERSION: u8 = 1;
+const EXTENSION: &str = "chtb";
+//file bytes from here up start a run
+const RUN: u8 = 128;
+
+//solver states, anything else is the distance to mate in plies plus one
+const UNKNOWN: u8 = 0;
+const DRAWN: u8 = 255;
+//marks a child outside the table being solved, its state in the low bits
+const EXTERNAL: u32 = 1 << 31;
+
+//the material on each side besides the kings, and the tables that captures and
+//promotions lead into
+pub struct Ending {
+    pub name: &'static str,
+    pub white: &'static [PieceKind],
+    pub black: &'static [PieceKind],
+    pub requires: &'static [&'static Ending],
+}
+
+pub static KQK: Ending = Ending {
+    name: "KQK",
+    white: &[PieceKind::Queen],
+    black: &[],
+    requires: &[],
+};
+
+pub static KRK: Ending = Ending {
+    name: "KRK",
+    white: &[PieceKind::Rook],
+    black: &[],
+    requires: &[],
+};
+
+//underpromotions to a minor piece cannot win
+pub static KPK: Ending = Ending {
+    name: "KPK",
+    white: &[PieceKind::Pawn],
+    black: &[],
+    requires: &[&KQK, &KRK],
+};
+
+pub static KBNK: Ending = Ending {
+    name: "KBNK",
+    white: &[PieceKind::Bishop, PieceKind::Knight],
+    black: &[],
+    requires: &[],
+};
+
+pub static KQKR: Ending = Ending {
+    name: "KQKR",
+    white: &[PieceKind::Queen],
+    black: &[PieceKind::Rook],
+    requires: &[&KQK, &KRK],
+};
+
+pub static ENDINGS: [&Ending; 5] = [&KQK, &KRK, &KPK, &KBNK, &KQKR];
+
+//the a1-d1-d4 triangle, where symmetry can put the white king of a pawnless ending
+const fn triangle() -> ([u8; 10], [u8; 64]) {
+    let mut squares = [0; 10];
+    let mut index = [u8::MAX; 64];
+    let mut count = 0;
+    let mut sq = 0;
+    while sq < 64 {
+        let (x, y) = (sq % 8, sq / 8);
+        if x <= 3 && y <= x {
+            squares[count] = sq as u8;
+            index[sq] = count as u8;
+            count += 1;
+        }
+        sq += 1;
+    }
+    (squares, index)
+}
+
+const TRIANGLE: ([u8; 10], [u8; 64]) = triangle();
+
+fn flip_file(sq: u8) -> u8 {
+    sq ^ 7
+}
+
+fn flip_rank(sq: u8) -> u8 {
+    sq ^ 56
+}
+
+fn transpose(sq: u8) -> u8 {
+    (sq % 8) * 8 + sq / 8
+}
+
+impl Ending {
+    fn has_pawns(&self) -> bool {
+        self.white.contains(&PieceKind::Pawn) || self.black.contains(&PieceKind::Pawn)
+    }
+
+    //squares the white king can be on after symmetry
+    fn king_squares(&self) -> usize {
+        match self.has_pawns() {
+            true => 32,
+            false => 10,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        let others = self.white.len() + self.black.len();
+        2 * self.king_squares() * 64usize.pow(others as u32 + 1)
+    }
+
+    fn pieces(&self) -> impl Iterator<Item = Piece> + '_ {
+        let white = self
+            .white
+            .iter()
+            .map(|&kind| Piece::new(kind, Color::White));
+        let black = self
+            .black
+            .iter()
+            .map(|&kind| Piece::new(kind, Color::Black));
+        white.chain(black)
+    }
+
+    //whether the board has this material, and if so whether with the colours
+    //the other way round
+    fn orientation(&self, board: &Board) -> Option<bool> {
+        let material = |color| {
+            let mut kinds: Vec<PieceKind> = board
+                .get_pieces(color)
+                .into_iter()
+                .map(|(_, piece)| piece.kind)
+                .filter(|&kind| kind != PieceKind::King)
+                .collect();
+            kinds.sort_by_key(|kind| kind.to_char());
+            kinds
+        };
+        let sorted = |kinds: &[PieceKind]| {
+            let mut kinds = kinds.to_vec();
+            kinds.sort_by_key(|kind| kind.to_char());
+            kinds
+        };
+        let (white, black) = (material(Color::White), material(Color::Black));
+        if white == sorted(self.white) && black == sorted(self.black) {
+            Some(false)
+        } else if white == sorted(self.black) && black == sorted(self.white) {
+            Some(true)
+        } else {
+            None
+        }
+    }
+
+    //where the position is in the table, after turning the board so white has
+    //this ending's white pieces and its king is on a canonical square
+    fn index_of(&self, board: &Board) -> Option<usize> {
+        let flipped = self.orientation(board)?;
+        let orient = |color: Color| if flipped { !color } else { color };
+        let square = |sq: &Square| {
+            let sq = bitboard::index(sq) as u8;
+            if flipped {
+                flip_rank(sq)
+            } else {
+                sq
+            }
+        };
+
+        let mut squares = vec![
+            square(&board.king_square(orient(Color::White))),
+            square(&board.king_square(orient(Color::Black))),
+        ];
+        let mut used = 0;
+        for piece in self.pieces() {
+            //each square is only taken once when there are two of a kind
+            let sq = bitboard::squares(board.pieces(piece.kind, orient(piece.color)))
+                .map(|sq| square(&sq))
+                .find(|sq| used & (1u64 << sq) == 0)?;
+            used |= 1 << sq;
+            squares.push(sq);
+        }
+
+        //mirror the king onto files a-d, and without pawns into the triangle
+        let mut king = squares[0];
+        let mut transforms: Vec<fn(u8) -> u8> = Vec::new();
+        if king % 8 > 3 {
+            transforms.push(flip_file);
+            king = flip_file(king);
+        }
+        if !self.has_pawns() {
+            if king / 8 > 3 {
+                transforms.push(flip_rank);
+                king = flip_rank(king);
+            }
+            if king / 8 > king % 8 {
+                transforms.push(transpose);
+            }
+        }
+        for transform in transforms {
+            squares.iter_mut().for_each(|sq| *sq = transform(*sq));
+        }
+
+        let king_index = match self.has_pawns() {
+            true => (squares[0] / 8 * 4 + squares[0] % 8) as usize,
+            false => TRIANGLE.1[squares[0] as usize] as usize,
+        };
+        let side = orient(board.side_to_move()) == Color::Black;
+        let mut index = side as usize * self.king_squares() + king_index;
+        for &sq in &squares[1..] {
+            index = index * 64 + sq as usize;
+        }
+        Some(index)
+    }
+
+    //the position at `index`, none for one that cannot happen
+    fn board_at(&self, mut index: usize) -> Option<Board> {
+        let pieces: Vec<Piece> = self.pieces().collect();
+        let mut squares = vec![0; pieces.len() + 1];
+        for sq in squares.iter_mut().rev() {
+            *sq = (index % 64) as u8;
+            index /= 64;
+        }
+        let king_index = index % self.king_squares();
+        let king = match self.has_pawns() {
+            true => (king_index / 4 * 8 + king_index % 4) as u8,
+            false => TRIANGLE.0[king_index],
+        };
+        let side = match index / self.king_squares() {
+            0 => Color::White,
+            _ => Color::Black,
+        };
+
+        let mut used = 1u64 << king;
+        let mut builder = BoardBuilder::new()
+            .piece(
+                Square::from((king % 8, king / 8)),
+                Piece::new(PieceKind::King, Color::White),
+            )
+            .side_to_move(side);
+        let kings = std::iter::once(Piece::new(PieceKind::King, Color::Black));
+        for (sq, piece) in squares.into_iter().zip(kings.chain(pieces)) {
+            if used & (1 << sq) != 0 {
+                return None;
+            }
+            used |= 1 << sq;
+            builder = builder.piece(Square::from((sq % 8, sq / 8)), piece);
+        }
+        builder.build().ok()
+    }
+}
+
+//distances to mate for one ending, in plies from the side to move
+#[derive(Clone)]
+pub struct EndgameTable {
+    ending: &'static Ending,
+    //0 for a draw, otherwise plies plus one, odd plies winning and even losing
+    data: Vec<u8>,
+}
+
+impl EndgameTable {
+    fn value(&self, index: usize) -> (Wdl, u32) {
+        match self.data[index] {
+            0 => (Wdl::Draw, 0),
+            value => {
+                let plies = value as u32 - 1;
+                match plies % 2 {
+                    1 => (Wdl::Win, plies),
+                    _ => (Wdl::Loss, plies),
+                }
+            }
+        }
+    }
+
+    fn max_plies(&self) -> u32 {
+        self.data
+            .iter()
+            .max()
+            .map_or(0, |&x| x.saturating_sub(1) as u32)
+    }
+
+    //magic, version, name and position count, then the values with a byte of
+    //128 or more in front of any repeated or large one giving how many there are
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        bytes.push(self.ending.name.len() as u8);
+        bytes.extend_from_slice(self.ending.name.as_bytes());
+        bytes.extend_from_slice(&(self.data.len() as u32).to_le_bytes());
+        for run in self.data.chunk_by(|a, b| a == b) {
+            for part in run.chunks(RUN as usize) {
+                if part.len() > 1 || part[0] >= RUN {
+                    bytes.push(RUN + (part.len() - 1) as u8);
+                }
+                bytes.push(part[0]);
+            }
+        }
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<EndgameTable> {
+        if bytes.get(0..4)? != MAGIC || *bytes.get(4)? != VERSION {
+            return None;
+        }
+        let name_len = *bytes.get(5)? as usize;
+        let name = std::str::from_utf8(bytes.get(6..6 + name_len)?).ok()?;
+        let ending = *ENDINGS.iter().find(|ending| ending.name == name)?;
+        let size_at = 6 + name_len;
+        let size = u32::from_le_bytes(bytes.get(size_at..size_at + 4)?.try_into().ok()?);
+        if size as usize != ending.size() {
+            return None;
+        }
+        let mut data = Vec::with_capacity(ending.size());
+        let mut values = bytes.get(size_at + 4..)?.iter();
+        while let Some(&byte) = values.next() {
+            match byte {
+                value if value < RUN => data.push(value),
+                len => {
+                    let value = *values.next()?;
+                    data.extend(std::iter::repeat_n(value, (len - RUN) as usize + 1));
+                }
+            }
+        }
+        match data.len() == ending.size() {
+            true => Some(EndgameTable { ending, data }),
+            false => None,
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct RetrogradeTablebase {
+    tables: Vec<EndgameTable>,
+}
+
+impl RetrogradeTablebase {
+    pub fn new() -> RetrogradeTablebase {
+        RetrogradeTablebase::default()
+    }
+
+    //loads every table saved in `dir`, skipping files that are not valid tables
+    pub fn open(dir: &Path) -> RetrogradeTablebase {
+        let mut tablebase = RetrogradeTablebase::new();
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return tablebase;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().and_then(|x| x.to_str()) != Some(EXTENSION) {
+                continue;
+            }
+            if let Some(table) = std::fs::read(&path)
+                .ok()
+                .and_then(|bytes| EndgameTable::from_bytes(&bytes))
+            {
+                if !tablebase.has(table.ending) {
+                    tablebase.tables.push(table);
+                }
+            }
+        }
+        tablebase
+    }
+
+    pub fn save(&self, dir: &Path) -> std::io::Result<()> {
+        std::fs::create_dir_all(dir)?;
+        for table in &self.tables {
+            let path = dir.join(format!("{}.{}", table.ending.name, EXTENSION));
+            std::fs::File::create(path)?.write_all(&table.to_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.tables.len()
+    }
+
+    pub fn has(&self, ending: &Ending) -> bool {
+        self.tables
+            .iter()
+            .any(|table| std::ptr::eq(table.ending, ending))
+    }
+
+    //the result for the side to move and how many plies until mate, none for
+    //material without a table
+    pub fn lookup(&self, board: &Board) -> Option<(Wdl, u32)> {
+        if board.is_insufficient_material() {
+            return Some((Wdl::Draw, 0));
+        }
+        if board.has_castling_rights() {
+            return None;
+        }
+        self.tables.iter().find_map(|table| {
+            let index = table.ending.index_of(board)?;
+            Some(table.value(index))
+        })
+    }
+
+    //works out `ending` and anything it leads into that is not here already
+    pub fn solve(&mut self, ending: &'static Ending) {
+        for &required in ending.requires {
+            if !self.has(required) {
+                self.solve(required);
+            }
+        }
+        if !self.has(ending) {
+            let table = self.solve_table(ending);
+            self.tables.push(table);
+        }
+    }
+
+    fn state(value: Option<(Wdl, u32)>) -> u8 {
+        match value {
+            Some((Wdl::Win | Wdl::Loss, plies)) => plies as u8 + 1,
+            _ => DRAWN,
+        }
+    }
+
+    fn solve_table(&self, ending: &'static Ending) -> EndgameTable {
+        let size = ending.size();
+        let mut state = vec![UNKNOWN; size];
+        //every position's moves, as indices into the table or the state they
+        //lead to outside it
+        let mut offsets = Vec::with_capacity(size + 1);
+        let mut children = Vec::new();
+        for (index, value) in state.iter_mut().enumerate() {
+            offsets.push(children.len());
+            let Some(mut board) = ending.board_at(index) else {
+                *value = DRAWN;
+                continue;
+            };
+            let color = board.side_to_move();
+            let moves = board.get_moves(color);
+            if moves.is_empty() {
+                *value = match board.king_in_check(color) {
+                    true => 1,
+                    false => DRAWN,
+                };
+                continue;
+            }
+            for mv in moves {
+                let undo = board.exec_move(&mv);
+                children.push(match ending.index_of(&board) {
+                    Some(child) => child as u32,
+                    None => EXTERNAL | Self::state(self.lookup(&board)) as u32,
+                });
+                board.unexec_move(&mv, undo);
+            }
+        }
+        offsets.push(children.len());
+
+        //a position is won in n plies once a move reaches one lost in n - 1, and
+        //lost once every move reaches a won one. Only results from earlier passes
+        //count so each is found at its true distance
+        let horizon = ending
+            .requires
+            .iter()
+            .filter_map(|&required| {
+                self.tables
+                    .iter()
+                    .find(|x| std::ptr::eq(x.ending, required))
+            })
+            .map(|table| table.max_plies())
+            .max()
+            .unwrap_or(0);
+        let mut last_change = 0;
+        let mut plies = 1;
+        while plies <= last_change.max(horizon) + 2 && plies < DRAWN as u32 - 1 {
+            for index in 0..size {
+                if state[index] != UNKNOWN {
+                    continue;
+                }
+                let mut fastest_win = None;
+                let mut slowest_loss = Some(0);
+                for &child in &children[offsets[index]..offsets[index + 1]] {
+                    let value = match child & EXTERNAL {
+                        0 => state[child as usize],
+                        _ => (child & 0xFF) as u8,
+                    };
+                    let known = value != UNKNOWN && value != DRAWN && (value as u32) <= plies;
+                    if !known {
+                        slowest_loss = None;
+                        continue;
+                    }
+                    let child_plies = value as u32 - 1;
+                    match child_plies % 2 {
+                        0 => {
+                            fastest_win =
+                                Some(fastest_win.map_or(child_plies, |x: u32| x.min(child_plies)));
+                            slowest_loss = None;
+                        }
+                        _ => slowest_loss = slowest_loss.map(|x: u32| x.max(child_plies)),
+                    }
+                }
+                if let Some(child_plies) = fastest_win.or(slowest_loss) {
+                    state[index] = child_plies as u8 + 2;
+                    last_change = plies;
+                }
+            }
+            plies += 1;
+        }
+
+        for value in state.iter_mut() {
+            if *value == UNKNOWN || *value == DRAWN {
+                *value = 0;
+            }
+        }
+        EndgameTable {
+            ending,
+            data: state,
+        }
+    }
+
+    //the move keeping the best result, mating soonest or being mated latest
+    pub fn best_move(&self, board: &Board) -> Option<ProbeResult> {
+        let (wdl, plies) = self.lookup(board)?;
+        let mut board = board.clone();
+        let color = board.side_to_move();
+        let mut best: Option<(Wdl, i64, Move)> = None;
+        for mv in board.get_moves(color) {
+            let undo = board.exec_move(&mv);
+            let child = self.lookup(&board);
+            board.unexec_move(&mv, undo);
+            let (child_wdl, child_plies) = child?;
+            let rank = match child_wdl {
+                Wdl::Loss => -(child_plies as i64),
+                Wdl::Win => child_plies as i64,
+                _ => 0,
+            };
+            if best
+                .is_none_or(|(best_wdl, best_rank, _)| (-child_wdl, rank) > (best_wdl, best_rank))
+            {
+                best = Some((-child_wdl, rank, mv));
+            }
+        }
+        let (_, _, mv) = best?;
+        Some(ProbeResult {
+            wdl,
+            mv,
+            dtz: None,
+            dtm: (wdl != Wdl::Draw).then_some(plies as i32),
+        })
+    }
+}
+
+impl TablebaseProvider for RetrogradeTablebase {
+    fn probe(&mut self, board: &Board) -> Result<ProbeResult, TablebaseError> {
+        self.best_move(board).ok_or(TablebaseError::NotCovered)
+    }
+
+    fn probe_wdl(&mut self, board: &mut Board) -> Result<Wdl, TablebaseError> {
+        self.lookup(board)
+            .map(|(wdl, _)| wdl)
+            .ok_or(TablebaseError::NotCovered)
+    }
+}
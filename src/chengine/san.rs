@@ -0,0 +1,139 @@
+use crate::chengine::*;
+
+fn is_castle(board: &Board, mv: &Move) -> bool {
+    board
+        .piece_at(&mv.from)
+        .is_some_and(|piece| piece.kind == PieceKind::King)
+        && (mv.from.x as i32 - mv.to.x as i32).abs() == 2
+}
+
+//the move written in standard algebraic notation, `mv` must be legal on `board`
+pub fn to_san(board: &Board, mv: &Move) -> String {
+    let piece = board.piece_at(&mv.from).expect("No piece to move");
+    let mut san = if is_castle(board, mv) {
+        match mv.to.x > mv.from.x {
+            true => "O-O".to_string(),
+            false => "O-O-O".to_string(),
+        }
+    } else {
+        let capture = board.occupied(&mv.to)
+            || (piece.kind == PieceKind::Pawn && Some(mv.to) == board.en_passant());
+        let mut san = String::new();
+        if piece.kind == PieceKind::Pawn {
+            if capture {
+                san.push((b'a' + mv.from.x) as char);
+            }
+        } else {
+            san.push(piece.kind.to_char().to_ascii_uppercase());
+            //other pieces of the same kind that could also reach the square
+            let rivals: Vec<Move> = board
+                .get_moves(piece.color)
+                .into_iter()
+                .filter(|other| {
+                    other.to == mv.to
+                        && other.from != mv.from
+                        && board
+                            .piece_at(&other.from)
+                            .is_some_and(|x| x.kind == piece.kind)
+                })
+                .collect();
+            if !rivals.is_empty() {
+                let from = mv.from.to_string();
+                if rivals.iter().all(|other| other.from.x != mv.from.x) {
+                    san.push_str(&from[..1]);
+                } else if rivals.iter().all(|other| other.from.y != mv.from.y) {
+                    san.push_str(&from[1..]);
+                } else {
+                    san.push_str(&from);
+                }
+            }
+        }
+        if capture {
+            san.push('x');
+        }
+        san.push_str(&mv.to.to_string());
+        if let Some(kind) = mv.promotion {
+            san.push('=');
+            san.push(kind.to_char().to_ascii_uppercase());
+        }
+        san
+    };
+
+    let mut after = board.clone();
+    after.exec_move(mv);
+    let opponent = !piece.color;
+    if after.king_in_check(opponent) {
+        san.push(match after.get_moves(opponent).is_empty() {
+            true => '#',
+            false => '+',
+        });
+    }
+    san
+}
+
+//finds the legal move written in standard algebraic notation, checks and annotations are optional
+pub fn parse_san(board: &Board, san: &str) -> Option<Move> {
+    let color = board.side_to_move();
+    let text = san.trim().trim_end_matches(['+', '#', '!', '?']);
+    let moves = board.get_moves(color);
+
+    let castle = match text {
+        "O-O" | "0-0" => Some(true),
+        "O-O-O" | "0-0-0" => Some(false),
+        _ => None,
+    };
+    if let Some(kingside) = castle {
+        return moves
+            .into_iter()
+            .find(|mv| is_castle(board, mv) && (mv.to.x > mv.from.x) == kingside);
+    }
+
+    let mut chars: Vec<char> = text.chars().filter(|&c| c != 'x' && c != '=').collect();
+    let kind = match chars.first()? {
+        c @ ('K' | 'Q' | 'R' | 'B' | 'N') => {
+            let kind = PieceKind::from_char(*c)?;
+            chars.remove(0);
+            kind
+        }
+        _ => PieceKind::Pawn,
+    };
+    //promotions are uppercase like every other piece, a lowercase b would be a file
+    let promotion = match chars.last()? {
+        c @ ('Q' | 'R' | 'B' | 'N') if kind == PieceKind::Pawn => {
+            let promotion = PieceKind::from_char(*c);
+            chars.pop();
+            promotion
+        }
+        _ => None,
+    };
+    if chars.len() < 2 || chars.len() > 4 {
+        return None;
+    }
+    let to = Square::new(&chars[chars.len() - 2..].iter().collect::<String>())?;
+    let mut file = None;
+    let mut rank = None;
+    for c in &chars[..chars.len() - 2] {
+        match c {
+            'a'..='h' => file = Some(*c as u8 - b'a'),
+            '1'..='8' => rank = Some(*c as u8 - b'1'),
+            _ => return None,
+        }
+    }
+
+    let mut candidates = moves.into_iter().filter(|mv| {
+        mv.to == to
+            && mv.promotion == promotion
+            && file.is_none_or(|x| mv.from.x == x)
+            && rank.is_none_or(|y| mv.from.y == y)
+            && board
+                .piece_at(&mv.from)
+                .is_some_and(|piece| piece.kind == kind)
+            && !is_castle(board, mv)
+    });
+    let found = candidates.next()?;
+    //an ambiguous move is not a move
+    match candidates.next() {
+        Some(_) => None,
+        None => Some(found),
+    }
+}
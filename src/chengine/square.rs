@@ -20,6 +20,7 @@ impl Square {
             } - 49) as u8,
         })
     }
+    #[allow(dead_code)]
     pub fn disp(&self) -> String {
         format!(
             "{}{}",
@@ -29,12 +30,9 @@ impl Square {
     }
 }
 
-impl std::convert::Into<Square> for (u8, u8) {
-    fn into(self) -> Square {
-        Square {
-            x: self.0,
-            y: self.1,
-        }
+impl std::convert::From<(u8, u8)> for Square {
+    fn from(val: (u8, u8)) -> Self {
+        Square { x: val.0, y: val.1 }
     }
 }
 
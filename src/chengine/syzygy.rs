@@ -0,0 +1,1102 @@
+//syzygy endgame tablebases read straight from .rtbw (win/draw/loss) and .rtbz
+//(distance to zeroing) files, following the layout of the reference prober
+use crate::chengine::*;
+use lazy_static::lazy_static;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+const WDL_MAGIC: [u8; 4] = [0x71, 0xE8, 0x23, 0x5D];
+const DTZ_MAGIC: [u8; 4] = [0xD7, 0x66, 0x0C, 0xA5];
+const MAX_PIECES: usize = 7;
+
+//per table flags
+const STM: u8 = 1;
+const MAPPED: u8 = 2;
+const WIN_PLIES: u8 = 4;
+const LOSS_PLIES: u8 = 8;
+const WIDE: u8 = 16;
+const SINGLE_VALUE: u8 = 128;
+
+//distance to zeroing of the move that got here by capturing or pushing a pawn
+fn dtz_before_zeroing(wdl: Wdl) -> i32 {
+    match wdl {
+        Wdl::Win => 1,
+        Wdl::CursedWin => 101,
+        Wdl::Draw => 0,
+        Wdl::BlessedLoss => -101,
+        Wdl::Loss => -1,
+    }
+}
+
+fn file_of(sq: usize) -> usize {
+    sq & 7
+}
+
+fn rank_of(sq: usize) -> usize {
+    sq >> 3
+}
+
+//how far a square is above the a1-h8 diagonal
+fn off_diagonal(sq: usize) -> i32 {
+    rank_of(sq) as i32 - file_of(sq) as i32
+}
+
+//index tables shared by every table, built once
+struct Encoding {
+    //b1-h1-h7 triangle to 0..27
+    map_b1h1h7: [u64; 64],
+    //a1-d1-d4 triangle to 0..9, diagonal squares last
+    map_a1d1d4: [u64; 64],
+    //the 462 ways to place two kings with the first in the a1-d1-d4 triangle
+    map_kk: [[u64; 64]; 10],
+    binomial: [[u64; 64]; MAX_PIECES],
+    //a2-h7 to 0..47, edge files and low ranks highest
+    map_pawns: [u64; 64],
+    lead_pawn_idx: [[u64; 64]; 6],
+    lead_pawns_size: [[u64; 4]; 6],
+}
+
+impl Encoding {
+    fn new() -> Encoding {
+        let mut e = Encoding {
+            map_b1h1h7: [0; 64],
+            map_a1d1d4: [0; 64],
+            map_kk: [[0; 64]; 10],
+            binomial: [[0; 64]; MAX_PIECES],
+            map_pawns: [0; 64],
+            lead_pawn_idx: [[0; 64]; 6],
+            lead_pawns_size: [[0; 4]; 6],
+        };
+
+        let mut code = 0;
+        for sq in 0..64 {
+            if off_diagonal(sq) < 0 {
+                e.map_b1h1h7[sq] = code;
+                code += 1;
+            }
+        }
+
+        let mut diagonal = Vec::new();
+        code = 0;
+        for sq in 0..=27 {
+            if off_diagonal(sq) < 0 && file_of(sq) <= 3 {
+                e.map_a1d1d4[sq] = code;
+                code += 1;
+            } else if off_diagonal(sq) == 0 && file_of(sq) <= 3 {
+                diagonal.push(sq);
+            }
+        }
+        for sq in diagonal {
+            e.map_a1d1d4[sq] = code;
+            code += 1;
+        }
+
+        //with the first king on the diagonal the second may not be above it
+        let mut both_on_diagonal = Vec::new();
+        code = 0;
+        for idx in 0..10 {
+            //b1 is the only square mapped to 0
+            for s1 in (0..=27).filter(|&s1| e.map_a1d1d4[s1] == idx && (idx != 0 || s1 == 1)) {
+                for s2 in 0..64 {
+                    let touching = s1 == s2 || bitboard::KING_ATTACKS[s1] & (1 << s2) != 0;
+                    if touching || (off_diagonal(s1) == 0 && off_diagonal(s2) > 0) {
+                        continue;
+                    } else if off_diagonal(s1) == 0 && off_diagonal(s2) == 0 {
+                        both_on_diagonal.push((idx as usize, s2));
+                    } else {
+                        e.map_kk[idx as usize][s2] = code;
+                        code += 1;
+                    }
+                }
+            }
+        }
+        for (idx, s2) in both_on_diagonal {
+            e.map_kk[idx][s2] = code;
+            code += 1;
+        }
+
+        e.binomial[0][0] = 1;
+        for n in 1..64 {
+            for k in 0..MAX_PIECES.min(n + 1) {
+                e.binomial[k][n] = if k > 0 { e.binomial[k - 1][n - 1] } else { 0 }
+                    + if k < n { e.binomial[k][n - 1] } else { 0 };
+            }
+        }
+
+        //tables are split by the file of the leading pawn, so each file restarts at 0
+        let mut available = 47;
+        for lead in 1..=5 {
+            for file in 0..4 {
+                let mut idx = 0;
+                for rank in 1..=6 {
+                    let sq = rank * 8 + file;
+                    if lead == 1 {
+                        e.map_pawns[sq] = available;
+                        e.map_pawns[sq ^ 7] = available.saturating_sub(1);
+                        available = available.saturating_sub(2);
+                    }
+                    e.lead_pawn_idx[lead][sq] = idx;
+                    idx += e.binomial[lead - 1][e.map_pawns[sq] as usize];
+                }
+                e.lead_pawns_size[lead][file] = idx;
+            }
+        }
+        e
+    }
+}
+
+lazy_static! {
+    static ref ENCODING: Encoding = Encoding::new();
+}
+
+//an open table file, read a page at a time as lookups need it rather than all at once
+#[derive(Debug)]
+struct TableFile {
+    file: RefCell<File>,
+    len: usize,
+    pages: RefCell<HashMap<usize, Vec<u8>>>,
+}
+
+impl TableFile {
+    const PAGE: usize = 4096;
+    //pages kept before the cache is dropped and refilled
+    const MAX_PAGES: usize = 1024;
+
+    fn open(path: &Path) -> Option<TableFile> {
+        let file = File::open(path).ok()?;
+        let len = file.metadata().ok()?.len() as usize;
+        Some(TableFile {
+            file: RefCell::new(file),
+            len,
+            pages: RefCell::new(HashMap::new()),
+        })
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn byte(&self, at: usize) -> Option<u8> {
+        if at >= self.len {
+            return None;
+        }
+        let page = at / Self::PAGE;
+        let mut pages = self.pages.borrow_mut();
+        if !pages.contains_key(&page) {
+            let start = page * Self::PAGE;
+            let mut buffer = vec![0; Self::PAGE.min(self.len - start)];
+            let mut file = self.file.borrow_mut();
+            file.seek(SeekFrom::Start(start as u64)).ok()?;
+            file.read_exact(&mut buffer).ok()?;
+            if pages.len() >= Self::MAX_PAGES {
+                pages.clear();
+            }
+            pages.insert(page, buffer);
+        }
+        pages[&page].get(at % Self::PAGE).copied()
+    }
+}
+
+fn read_u16(data: &TableFile, at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes([data.byte(at)?, data.byte(at + 1)?]))
+}
+
+fn read_u32(data: &TableFile, at: usize) -> Option<u32> {
+    Some(read_u16(data, at)? as u32 | (read_u16(data, at + 2)? as u32) << 16)
+}
+
+//big endian reads of the compressed bit stream, which may run past the last block
+fn read_bits(data: &TableFile, at: usize, bytes: usize) -> u64 {
+    (0..bytes).fold(0, |acc, i| {
+        (acc << 8) | data.byte(at + i).unwrap_or(0) as u64
+    })
+}
+
+//one compressed table, for a side to move and leading pawn file
+#[derive(Debug, Clone, Default)]
+struct PairsData {
+    flags: u8,
+    block_size: usize,
+    span: u64,
+    num_blocks: usize,
+    min_sym_len: u8,
+    //offsets into the file
+    lowest_sym: usize,
+    btree: usize,
+    block_length: usize,
+    block_length_size: usize,
+    sparse_index: usize,
+    sparse_index_size: usize,
+    data: usize,
+    base64: Vec<u64>,
+    //how many values (minus one) each symbol expands to
+    symlen: Vec<u16>,
+    //the order pieces are encoded in, 1-6 for white pawn to king and 9-14 for black
+    pieces: [u8; MAX_PIECES],
+    group_idx: [u64; MAX_PIECES + 1],
+    group_len: [usize; MAX_PIECES + 1],
+    //byte offsets into the dtz map for win, loss, cursed win and blessed loss
+    map_idx: [usize; 4],
+}
+
+impl PairsData {
+    fn left(&self, data: &TableFile, sym: usize) -> Option<usize> {
+        let at = self.btree + sym * 3;
+        Some(((data.byte(at + 1)? as usize & 0xF) << 8) | data.byte(at)? as usize)
+    }
+
+    fn right(&self, data: &TableFile, sym: usize) -> Option<usize> {
+        let at = self.btree + sym * 3;
+        Some(((data.byte(at + 2)? as usize) << 4) | (data.byte(at + 1)? as usize >> 4))
+    }
+
+    fn set_symlen(&mut self, data: &TableFile, sym: usize, visited: &mut [bool]) -> Option<u16> {
+        visited[sym] = true;
+        let right = self.right(data, sym)?;
+        if right == 0xFFF {
+            return Some(0);
+        }
+        let left = self.left(data, sym)?;
+        for child in [left, right] {
+            if !*visited.get(child)? {
+                self.symlen[child] = self.set_symlen(data, child, visited)?;
+            }
+        }
+        Some(self.symlen[left] + self.symlen[right] + 1)
+    }
+
+    //reads the header of the compressed data, returning where the next one starts
+    fn read_sizes(&mut self, data: &TableFile, mut at: usize) -> Option<usize> {
+        self.flags = data.byte(at)?;
+        at += 1;
+        if self.flags & SINGLE_VALUE != 0 {
+            //every position has the same value, stored here
+            self.min_sym_len = data.byte(at)?;
+            return Some(at + 1);
+        }
+
+        let groups = self.group_len.iter().position(|&len| len == 0)?;
+        let size = self.group_idx[groups];
+        self.block_size = 1usize.checked_shl(data.byte(at)? as u32)?;
+        self.span = 1u64.checked_shl(data.byte(at + 1)? as u32)?;
+        self.sparse_index_size = size.div_ceil(self.span) as usize;
+        let padding = data.byte(at + 2)? as usize;
+        self.num_blocks = read_u32(data, at + 3)? as usize;
+        self.block_length_size = self.num_blocks + padding;
+        let max_sym_len = data.byte(at + 7)?;
+        self.min_sym_len = data.byte(at + 8)?;
+        at += 9;
+        if self.min_sym_len == 0 || max_sym_len < self.min_sym_len || max_sym_len > 32 {
+            return None;
+        }
+        self.lowest_sym = at;
+
+        //canonical huffman codes, longer codes have lower values so base64 decreases
+        let lengths = (max_sym_len - self.min_sym_len + 1) as usize;
+        self.base64 = vec![0; lengths];
+        for i in (0..lengths - 1).rev() {
+            let lowest = read_u16(data, self.lowest_sym + i * 2)? as u64;
+            let next = read_u16(data, self.lowest_sym + (i + 1) * 2)? as u64;
+            self.base64[i] = (self.base64[i + 1] + lowest).wrapping_sub(next) / 2;
+        }
+        for (i, base) in self.base64.iter_mut().enumerate() {
+            *base <<= 64 - i - self.min_sym_len as usize;
+        }
+        at += lengths * 2;
+
+        let symbols = read_u16(data, at)? as usize;
+        at += 2;
+        self.btree = at;
+        self.symlen = vec![0; symbols];
+        let mut visited = vec![false; symbols];
+        for sym in 0..symbols {
+            if !visited[sym] {
+                self.symlen[sym] = self.set_symlen(data, sym, &mut visited)?;
+            }
+        }
+        Some(at + symbols * 3 + (symbols & 1))
+    }
+
+    //the value stored at `idx`
+    fn decompress(&self, data: &TableFile, idx: u64) -> Option<i32> {
+        if self.flags & SINGLE_VALUE != 0 {
+            return Some(self.min_sym_len as i32);
+        }
+
+        //the sparse index points into the blocks every span values
+        let k = (idx / self.span) as usize;
+        if k >= self.sparse_index_size {
+            return None;
+        }
+        let mut block = read_u32(data, self.sparse_index + k * 6)? as usize;
+        let mut offset = read_u16(data, self.sparse_index + k * 6 + 4)? as i64;
+        offset += (idx % self.span) as i64 - (self.span / 2) as i64;
+
+        let length = |block: usize| -> Option<i64> {
+            if block >= self.block_length_size {
+                return None;
+            }
+            Some(read_u16(data, self.block_length + block * 2)? as i64)
+        };
+        while offset < 0 {
+            block = block.checked_sub(1)?;
+            offset += length(block)? + 1;
+        }
+        while offset > length(block)? {
+            offset -= length(block)? + 1;
+            block += 1;
+        }
+
+        //find the symbol holding our value in the block
+        let mut at = self.data + block * self.block_size;
+        let mut buffer = read_bits(data, at, 8);
+        let mut buffer_size = 64;
+        at += 8;
+        let min = self.min_sym_len as usize;
+        let mut sym;
+        loop {
+            let mut len = 0;
+            while buffer < *self.base64.get(len)? {
+                len += 1;
+            }
+            sym = ((buffer - self.base64[len]) >> (64 - len - min)) as u16;
+            sym = sym.wrapping_add(read_u16(data, self.lowest_sym + len * 2)?);
+            let count = *self.symlen.get(sym as usize)? as i64 + 1;
+            if offset < count {
+                break;
+            }
+            offset -= count;
+            buffer <<= len + min;
+            buffer_size -= len + min;
+            if buffer_size <= 32 {
+                buffer_size += 32;
+                buffer |= read_bits(data, at, 4) << (64 - buffer_size);
+                at += 4;
+            }
+        }
+
+        //symbols are pairs of symbols, walk down to the one value we want
+        let mut sym = sym as usize;
+        while self.symlen[sym] != 0 {
+            let left = self.left(data, sym)?;
+            let count = *self.symlen.get(left)? as i64 + 1;
+            if offset < count {
+                sym = left;
+            } else {
+                offset -= count;
+                sym = self.right(data, sym)?;
+            }
+        }
+        Some(self.left(data, sym)? as i32)
+    }
+}
+
+//what a table holds for the position
+enum Lookup {
+    Found(i32),
+    //dtz tables only store one side to move
+    OtherSide,
+}
+
+#[derive(Debug)]
+struct Table {
+    //the material with the stronger side first, e.g. KRvK
+    name: String,
+    dtz: bool,
+    data: TableFile,
+    piece_count: usize,
+    has_pawns: bool,
+    has_unique_pieces: bool,
+    //both sides have the same material
+    symmetric: bool,
+    //pawns of the leading side, then of the other
+    pawn_count: [usize; 2],
+    //[side to move][file of the leading pawn]
+    pairs: Vec<Vec<PairsData>>,
+    dtz_map: usize,
+}
+
+impl Table {
+    fn load(name: &str, data: TableFile, dtz: bool) -> Option<Table> {
+        let magic = if dtz { DTZ_MAGIC } else { WDL_MAGIC };
+        if data.len() % 64 != 16 || (0..4).any(|i| data.byte(i) != Some(magic[i])) {
+            return None;
+        }
+        let (white, black) = name.split_once('v')?;
+        let count = |side: &str, kind: PieceKind| {
+            side.chars()
+                .filter(|&c| PieceKind::from_char(c) == Some(kind))
+                .count()
+        };
+        let pawns = [count(white, PieceKind::Pawn), count(black, PieceKind::Pawn)];
+        //the side with fewer pawns leads, as that compresses better
+        let lead = match pawns[1] == 0 || (pawns[0] > 0 && pawns[1] >= pawns[0]) {
+            true => 0,
+            false => 1,
+        };
+        let mut table = Table {
+            name: name.to_string(),
+            dtz,
+            piece_count: white.len() + black.len(),
+            has_pawns: pawns[0] + pawns[1] > 0,
+            has_unique_pieces: [white, black].iter().any(|side| {
+                PieceKind::ALL
+                    .iter()
+                    .any(|&kind| kind != PieceKind::King && count(side, kind) == 1)
+            }),
+            symmetric: white == black,
+            pawn_count: [pawns[lead], pawns[1 - lead]],
+            pairs: Vec::new(),
+            dtz_map: 0,
+            data,
+        };
+        table.read_header()?;
+        Some(table)
+    }
+
+    fn read_header(&mut self) -> Option<()> {
+        const SPLIT: u8 = 1;
+        const HAS_PAWNS: u8 = 2;
+        let flags = self.data.byte(4)?;
+        if (flags & HAS_PAWNS != 0) != self.has_pawns || (flags & SPLIT != 0) == self.symmetric {
+            return None;
+        }
+        let mut at = 5;
+        let sides = if !self.dtz && !self.symmetric { 2 } else { 1 };
+        let files = if self.has_pawns { 4 } else { 1 };
+        //pawns on both sides
+        let both = self.has_pawns && self.pawn_count[1] > 0;
+        self.pairs = vec![vec![PairsData::default(); files]; sides];
+
+        for file in 0..files {
+            let first = self.data.byte(at)?;
+            let second = if both { self.data.byte(at + 1)? } else { 0xFF };
+            let order = [[first & 0xF, second & 0xF], [first >> 4, second >> 4]];
+            at += 1 + both as usize;
+            for k in 0..self.piece_count {
+                let byte = self.data.byte(at)?;
+                for (side, pairs) in self.pairs.iter_mut().enumerate() {
+                    pairs[file].pieces[k] = if side == 1 { byte >> 4 } else { byte & 0xF };
+                }
+                at += 1;
+            }
+            for (side, order) in order.into_iter().enumerate().take(sides) {
+                let mut pairs = std::mem::take(&mut self.pairs[side][file]);
+                self.set_groups(&mut pairs, order, file)?;
+                self.pairs[side][file] = pairs;
+            }
+        }
+        at += at & 1;
+
+        for file in 0..files {
+            for side in 0..sides {
+                at = self.pairs[side][file].read_sizes(&self.data, at)?;
+            }
+        }
+
+        if self.dtz {
+            self.dtz_map = at;
+            for file in 0..files {
+                let pairs = &mut self.pairs[0][file];
+                if pairs.flags & MAPPED == 0 {
+                    continue;
+                }
+                //four maps, one per non-draw result
+                for i in 0..4 {
+                    if pairs.flags & WIDE != 0 {
+                        at += at & 1;
+                        pairs.map_idx[i] = at + 2 - self.dtz_map;
+                        at += 2 * read_u16(&self.data, at)? as usize + 2;
+                    } else {
+                        pairs.map_idx[i] = at + 1 - self.dtz_map;
+                        at += self.data.byte(at)? as usize + 1;
+                    }
+                }
+            }
+            at += at & 1;
+        }
+
+        for file in 0..files {
+            for side in 0..sides {
+                let pairs = &mut self.pairs[side][file];
+                pairs.sparse_index = at;
+                at += pairs.sparse_index_size * 6;
+            }
+        }
+        for file in 0..files {
+            for side in 0..sides {
+                let pairs = &mut self.pairs[side][file];
+                pairs.block_length = at;
+                at += pairs.block_length_size * 2;
+            }
+        }
+        for file in 0..files {
+            for side in 0..sides {
+                let pairs = &mut self.pairs[side][file];
+                at = (at + 0x3F) & !0x3F;
+                pairs.data = at;
+                at += pairs.num_blocks * pairs.block_size;
+            }
+        }
+        match at <= self.data.len() {
+            true => Some(()),
+            false => None,
+        }
+    }
+
+    //splits the pieces into groups of the same piece and works out what each is worth
+    fn set_groups(&self, d: &mut PairsData, order: [u8; 2], file: usize) -> Option<()> {
+        let encoding = &*ENCODING;
+        let mut first_len: i32 = if self.has_pawns {
+            0
+        } else if self.has_unique_pieces {
+            3
+        } else {
+            2
+        };
+        let mut n = 0;
+        d.group_len[0] = 1;
+        for i in 1..self.piece_count {
+            first_len -= 1;
+            if first_len > 0 || d.pieces[i] == d.pieces[i - 1] {
+                d.group_len[n] += 1;
+            } else {
+                n += 1;
+                d.group_len[n] = 1;
+            }
+        }
+        n += 1;
+        d.group_len[n] = 0;
+
+        //the leading group is at order[0], the other side's pawns at order[1]
+        let both = self.has_pawns && self.pawn_count[1] > 0;
+        let mut next = if both { 2 } else { 1 };
+        let mut free = 64 - d.group_len[0] - if both { d.group_len[1] } else { 0 };
+        let mut idx: u64 = 1;
+        let mut k = 0;
+        while next < n || k == order[0] as usize || k == order[1] as usize {
+            if k == order[0] as usize {
+                d.group_idx[0] = idx;
+                idx *= if self.has_pawns {
+                    *encoding.lead_pawns_size.get(d.group_len[0])?.get(file)?
+                } else if self.has_unique_pieces {
+                    31332
+                } else {
+                    462
+                };
+            } else if k == order[1] as usize {
+                d.group_idx[1] = idx;
+                idx *= encoding.binomial.get(d.group_len[1])?[48 - d.group_len[0]];
+            } else {
+                d.group_idx[next] = idx;
+                idx *= encoding.binomial.get(d.group_len[next])?[free];
+                free -= d.group_len[next];
+                next += 1;
+            }
+            k += 1;
+        }
+        d.group_idx[n] = idx;
+        Some(())
+    }
+
+    fn pairs(&self, side: usize, file: usize) -> &PairsData {
+        let files = &self.pairs[side % self.pairs.len()];
+        &files[if self.has_pawns { file } else { 0 }]
+    }
+
+    //turns the board into an index into the table, `wdl` is only used by dtz tables
+    fn lookup(&self, board: &Board, wdl: Wdl) -> Option<Lookup> {
+        let encoding = &*ENCODING;
+        let black_to_move = board.side_to_move() == Color::Black;
+        //tables are stored with the stronger side as white, and symmetric ones only
+        //with white to move, so other positions are flipped to match
+        let flip =
+            (self.symmetric && black_to_move) || self.name != material_code(board, Color::White);
+        let flip_color = if flip { 8 } else { 0 };
+        let flip_squares = if flip { 56 } else { 0 };
+        let side = (flip ^ black_to_move) as usize;
+
+        let mut squares = Vec::with_capacity(MAX_PIECES);
+        let mut pieces = Vec::with_capacity(MAX_PIECES);
+        let mut lead_pawns = 0;
+        let mut file = 0;
+        if self.has_pawns {
+            //pawns of the leading side come first in every file's table
+            let pawn = self.pairs(0, 0).pieces[0] ^ flip_color;
+            let color = if pawn & 8 == 0 {
+                Color::White
+            } else {
+                Color::Black
+            };
+            lead_pawns = board.pieces(PieceKind::Pawn, color);
+            for sq in bitboard::squares(lead_pawns) {
+                squares.push(bitboard::index(&sq) ^ flip_squares);
+                pieces.push(pawn);
+            }
+            //the leading pawn is the one nearest the edge, then the lowest
+            let mut lead = 0;
+            for i in 1..squares.len() {
+                if encoding.map_pawns[squares[i]] > encoding.map_pawns[squares[lead]] {
+                    lead = i;
+                }
+            }
+            squares.swap(0, lead);
+            file = file_of(squares[0]).min(7 - file_of(squares[0]));
+        }
+        let lead_count = squares.len();
+
+        if self.dtz {
+            let flags = self.pairs(side, file).flags;
+            if (flags & STM) as usize != side && (self.has_pawns || !self.symmetric) {
+                return Some(Lookup::OtherSide);
+            }
+        }
+
+        for sq in bitboard::squares(board.occupancy() & !lead_pawns) {
+            let piece = board.piece_at(&sq)?;
+            let color = match piece.color {
+                Color::White => 0,
+                Color::Black => 8,
+            };
+            squares.push(bitboard::index(&sq) ^ flip_squares);
+            pieces.push((piece.kind.index() as u8 + 1 + color) ^ flip_color);
+        }
+        let size = squares.len();
+        let d = self.pairs(side, file);
+
+        //put the pieces in the order the table encodes them
+        for i in lead_count..size.saturating_sub(1) {
+            for j in i + 1..size {
+                if d.pieces[i] == pieces[j] {
+                    pieces.swap(i, j);
+                    squares.swap(i, j);
+                    break;
+                }
+            }
+        }
+
+        //mirror so the leading piece is on files a-d
+        if file_of(squares[0]) > 3 {
+            for sq in squares.iter_mut() {
+                *sq ^= 7;
+            }
+        }
+
+        let mut idx;
+        if self.has_pawns {
+            idx = encoding.lead_pawn_idx[lead_count][squares[0]];
+            squares[1..lead_count].sort_by_key(|&sq| encoding.map_pawns[sq]);
+            for (i, &sq) in squares.iter().enumerate().take(lead_count).skip(1) {
+                idx += encoding.binomial[i][encoding.map_pawns[sq] as usize];
+            }
+        } else {
+            //without pawns the board is also mirrored to keep the leading piece below rank 5
+            if rank_of(squares[0]) > 3 {
+                for sq in squares.iter_mut() {
+                    *sq ^= 56;
+                }
+            }
+            //and flipped along the a1-h8 diagonal so the first piece off it is below it
+            for i in 0..d.group_len[0] {
+                let off = off_diagonal(squares[i]);
+                if off == 0 {
+                    continue;
+                }
+                if off > 0 {
+                    for sq in squares[i..].iter_mut() {
+                        *sq = ((*sq >> 3) | (*sq << 3)) & 63;
+                    }
+                }
+                break;
+            }
+
+            if self.has_unique_pieces {
+                let (s0, s1, s2) = (squares[0], squares[1], squares[2]);
+                let adjust1 = (s1 > s0) as usize;
+                let adjust2 = (s2 > s0) as usize + (s2 > s1) as usize;
+                idx = if off_diagonal(s0) != 0 {
+                    (encoding.map_a1d1d4[s0] * 63 + (s1 - adjust1) as u64) * 62
+                        + (s2 - adjust2) as u64
+                } else if off_diagonal(s1) != 0 {
+                    (6 * 63 + rank_of(s0) as u64 * 28 + encoding.map_b1h1h7[s1]) * 62
+                        + (s2 - adjust2) as u64
+                } else if off_diagonal(s2) != 0 {
+                    6 * 63 * 62
+                        + 4 * 28 * 62
+                        + rank_of(s0) as u64 * 7 * 28
+                        + (rank_of(s1) - adjust1) as u64 * 28
+                        + encoding.map_b1h1h7[s2]
+                } else {
+                    6 * 63 * 62
+                        + 4 * 28 * 62
+                        + 4 * 7 * 28
+                        + rank_of(s0) as u64 * 7 * 6
+                        + (rank_of(s1) - adjust1) as u64 * 6
+                        + (rank_of(s2) - adjust2) as u64
+                };
+            } else {
+                idx = encoding.map_kk[encoding.map_a1d1d4[squares[0]] as usize][squares[1]];
+            }
+        }
+
+        //the remaining groups, each counted past the squares already taken
+        idx *= d.group_idx[0];
+        let mut start = d.group_len[0];
+        let mut remaining_pawns = self.has_pawns && self.pawn_count[1] > 0;
+        let mut next = 1;
+        while d.group_len[next] != 0 {
+            let len = d.group_len[next];
+            squares[start..start + len].sort_unstable();
+            let mut n = 0;
+            for i in 0..len {
+                let sq = squares[start + i];
+                let adjust = squares[..start].iter().filter(|&&x| sq > x).count();
+                let pawn_adjust = if remaining_pawns { 8 } else { 0 };
+                n += encoding.binomial[i + 1][sq - adjust - pawn_adjust];
+            }
+            remaining_pawns = false;
+            idx += n * d.group_idx[next];
+            start += len;
+            next += 1;
+        }
+
+        let value = d.decompress(&self.data, idx)?;
+        if !self.dtz {
+            return Some(Lookup::Found(value - 2));
+        }
+
+        //dtz values can be stored through a map, and in moves rather than plies
+        let d = self.pairs(0, file);
+        let mut value = value as usize;
+        if d.flags & MAPPED != 0 {
+            let map = self.dtz_map + d.map_idx[[1, 3, 0, 2, 0][(wdl.value() + 2) as usize]];
+            value = match d.flags & WIDE != 0 {
+                true => read_u16(&self.data, map + value * 2)? as usize,
+                false => self.data.byte(map + value)? as usize,
+            };
+        }
+        let value = value as i32;
+        let plies = match wdl {
+            Wdl::Win => d.flags & WIN_PLIES != 0,
+            Wdl::Loss => d.flags & LOSS_PLIES != 0,
+            _ => false,
+        };
+        Some(Lookup::Found(if plies { value } else { value * 2 } + 1))
+    }
+}
+
+//the pieces of one side as they appear in file names, e.g. KRP
+fn side_code(board: &Board, color: Color) -> String {
+    let mut code = String::new();
+    for kind in [
+        PieceKind::King,
+        PieceKind::Queen,
+        PieceKind::Rook,
+        PieceKind::Bishop,
+        PieceKind::Knight,
+        PieceKind::Pawn,
+    ] {
+        for _ in 0..board.pieces(kind, color).count_ones() {
+            code.push(kind.to_char().to_ascii_uppercase());
+        }
+    }
+    code
+}
+
+//material with `first` on the left of the v, e.g. KRvK
+fn material_code(board: &Board, first: Color) -> String {
+    format!("{}v{}", side_code(board, first), side_code(board, !first))
+}
+
+//a file that is loaded the first time a position needs it
+#[derive(Debug)]
+enum Source {
+    Missing,
+    Unloaded(PathBuf),
+    Loaded(Box<Table>),
+    Broken,
+}
+
+impl Source {
+    fn table(&mut self, name: &str, dtz: bool) -> Option<&Table> {
+        if let Source::Unloaded(path) = self {
+            *self = match TableFile::open(path).and_then(|data| Table::load(name, data, dtz)) {
+                Some(table) => Source::Loaded(Box::new(table)),
+                None => Source::Broken,
+            };
+        }
+        match self {
+            Source::Loaded(table) => Some(table),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct LocalTablebase {
+    //wdl and dtz files by material
+    tables: HashMap<String, (Source, Source)>,
+    max_pieces: usize,
+}
+
+impl LocalTablebase {
+    pub fn new() -> LocalTablebase {
+        LocalTablebase {
+            tables: HashMap::new(),
+            max_pieces: 0,
+        }
+    }
+
+    //finds every table in the directories of `path`, separated like PATH
+    pub fn open(path: &str) -> LocalTablebase {
+        let mut tablebase = LocalTablebase::new();
+        for dir in std::env::split_paths(path) {
+            let Ok(entries) = std::fs::read_dir(dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let path = entry.path();
+                let (Some(name), Some(extension)) = (
+                    path.file_stem().and_then(|x| x.to_str()),
+                    path.extension().and_then(|x| x.to_str()),
+                ) else {
+                    continue;
+                };
+                let dtz = match extension {
+                    "rtbw" => false,
+                    "rtbz" => true,
+                    _ => continue,
+                };
+                if !Self::valid_name(name) {
+                    continue;
+                }
+                let name = name.to_string();
+                let pieces = name.len() - 1;
+                let files = tablebase
+                    .tables
+                    .entry(name)
+                    .or_insert((Source::Missing, Source::Missing));
+                match dtz {
+                    false => files.0 = Source::Unloaded(path),
+                    true => files.1 = Source::Unloaded(path),
+                }
+                tablebase.max_pieces = tablebase.max_pieces.max(pieces);
+            }
+        }
+        tablebase
+    }
+
+    //e.g. KRPvKR, a king on each side first and at most seven pieces
+    fn valid_name(name: &str) -> bool {
+        let Some((white, black)) = name.split_once('v') else {
+            return false;
+        };
+        name.len() - 1 <= MAX_PIECES
+            && [white, black].iter().all(|side| {
+                side.starts_with('K')
+                    && side.matches('K').count() == 1
+                    && side.chars().all(|c| "KQRBNP".contains(c))
+            })
+    }
+
+    //how many materials have a win/draw/loss table
+    pub fn len(&self) -> usize {
+        self.tables
+            .values()
+            .filter(|(wdl, _)| !matches!(wdl, Source::Missing))
+            .count()
+    }
+
+    #[allow(dead_code)]
+    pub fn max_pieces(&self) -> usize {
+        self.max_pieces
+    }
+
+    //tables only cover positions without castling rights
+    fn covers(&self, board: &Board) -> bool {
+        board.piece_count as usize <= self.max_pieces && !board.has_castling_rights()
+    }
+
+    fn table(&mut self, board: &Board, dtz: bool) -> Option<&Table> {
+        let name = match self
+            .tables
+            .contains_key(&material_code(board, Color::White))
+        {
+            true => material_code(board, Color::White),
+            false => material_code(board, Color::Black),
+        };
+        let (wdl_source, dtz_source) = self.tables.get_mut(&name)?;
+        match dtz {
+            false => wdl_source.table(&name, false),
+            true => dtz_source.table(&name, true),
+        }
+    }
+
+    fn lookup_wdl(&mut self, board: &Board) -> Option<Wdl> {
+        //the bare kings are not worth a file
+        if board.piece_count == 2 {
+            return Some(Wdl::Draw);
+        }
+        match self.table(board, false)?.lookup(board, Wdl::Draw)? {
+            Lookup::Found(value) => Wdl::from_value(value),
+            Lookup::OtherSide => None,
+        }
+    }
+
+    fn legal_moves(board: &Board) -> Vec<Move> {
+        board.get_moves(board.side_to_move())
+    }
+
+    fn is_pawn_move(board: &Board, mv: &Move) -> bool {
+        board
+            .piece_at(&mv.from)
+            .is_some_and(|piece| piece.kind == PieceKind::Pawn)
+    }
+
+    //captures (and with `zeroing` pawn moves) are played out rather than read from
+    //the table, which ignores en passant. Also returns whether the best move zeroes
+    fn search(&mut self, board: &mut Board, zeroing: bool) -> Option<(Wdl, bool)> {
+        let moves = Self::legal_moves(board);
+        let total = moves.len();
+        let mut searched = 0;
+        let mut best = Wdl::Loss;
+        for mv in moves {
+            if !(mv.is_capture() || (zeroing && Self::is_pawn_move(board, &mv))) {
+                continue;
+            }
+            searched += 1;
+            let undo = board.exec_move(&mv);
+            let value = self.search(board, false).map(|(wdl, _)| -wdl);
+            board.unexec_move(&mv, undo);
+            let value = value?;
+            if value > best {
+                best = value;
+                if value == Wdl::Win {
+                    return Some((value, true));
+                }
+            }
+        }
+
+        //with every move searched the stored value is not needed, or even right
+        let no_more_moves = searched > 0 && searched == total;
+        let value = match no_more_moves {
+            true => best,
+            false => self.lookup_wdl(board)?,
+        };
+        match best >= value {
+            true => Some((best, best > Wdl::Draw || no_more_moves)),
+            false => Some((value, false)),
+        }
+    }
+
+    //the result with best play, ignoring the fifty move rule's effect on the rest
+    //of the game but not the current halfmove clock
+    pub fn probe_wdl(&mut self, board: &mut Board) -> Option<Wdl> {
+        if !self.covers(board) {
+            return None;
+        }
+        self.search(board, false).map(|(wdl, _)| wdl)
+    }
+
+    //plies to the next capture or pawn move with best play, negative when losing
+    //and 0 for a draw. Wins and losses past the fifty move rule are 100 further out
+    #[allow(dead_code)]
+    pub fn probe_dtz(&mut self, board: &mut Board) -> Option<i32> {
+        if !self.covers(board) {
+            return None;
+        }
+        self.dtz(board)
+    }
+
+    fn dtz(&mut self, board: &mut Board) -> Option<i32> {
+        let (wdl, zeroing_best) = self.search(board, true)?;
+        if wdl == Wdl::Draw {
+            return Some(0);
+        }
+        if zeroing_best {
+            return Some(dtz_before_zeroing(wdl));
+        }
+        if board.piece_count > 2 {
+            if let Lookup::Found(dtz) = self.table(board, true)?.lookup(board, wdl)? {
+                let cursed = matches!(wdl, Wdl::CursedWin | Wdl::BlessedLoss);
+                return Some((dtz + if cursed { 100 } else { 0 }) * wdl.signum());
+            }
+        }
+
+        //the table holds the other side to move, so look one ply ahead
+        let mut best = i32::MAX;
+        for mv in Self::legal_moves(board) {
+            let zeroing = mv.is_capture() || Self::is_pawn_move(board, &mv);
+            let undo = board.exec_move(&mv);
+            let dtz = match zeroing {
+                true => self
+                    .search(board, false)
+                    .map(|(wdl, _)| -dtz_before_zeroing(wdl)),
+                false => self.dtz(board).map(|dtz| -dtz),
+            };
+            let mates = dtz == Some(1)
+                && board.king_in_check(board.side_to_move())
+                && Self::legal_moves(board).is_empty();
+            board.unexec_move(&mv, undo);
+            let mut dtz = dtz?;
+            if mates {
+                best = 1;
+            }
+            if !zeroing {
+                dtz += dtz.signum();
+            }
+            if dtz < best && dtz.signum() == wdl.signum() {
+                best = dtz;
+            }
+        }
+        //without legal moves the side to move is mated
+        Some(if best == i32::MAX { -1 } else { best })
+    }
+
+    //the result for the side to move and the move that keeps it, winning as fast as
+    //possible and losing as slowly as possible
+    pub fn best_move(&mut self, board: &Board) -> Option<(Wdl, Move)> {
+        if !self.covers(board) {
+            return None;
+        }
+        let mut board = board.clone();
+        let wdl = self.search(&mut board, false)?.0;
+        let mut best: Option<(i32, Move)> = None;
+        for mv in Self::legal_moves(&board) {
+            let undo = board.exec_move(&mv);
+            let dtz = match board.halfmove_clock() {
+                0 => self
+                    .search(&mut board, false)
+                    .map(|(wdl, _)| -dtz_before_zeroing(wdl)),
+                _ => self.dtz(&mut board).map(|dtz| -dtz - dtz.signum()),
+            };
+            let mates =
+                board.king_in_check(board.side_to_move()) && Self::legal_moves(&board).is_empty();
+            board.unexec_move(&mv, undo);
+            let dtz = if mates { 1 } else { dtz? };
+            let rank = match dtz.signum() {
+                1 => 1000 - dtz,
+                -1 => -1000 - dtz,
+                _ => 0,
+            };
+            if best.is_none_or(|(best_rank, _)| rank > best_rank) {
+                best = Some((rank, mv));
+            }
+        }
+        best.map(|(_, mv)| (wdl, mv))
+    }
+}
+
+impl TablebaseProvider for LocalTablebase {
+    fn probe(&mut self, board: &Board) -> Result<ProbeResult, TablebaseError> {
+        let (wdl, mv) = self.best_move(board).ok_or(TablebaseError::NotCovered)?;
+        Ok(ProbeResult::new(wdl, mv))
+    }
+
+    fn probe_wdl(&mut self, board: &mut Board) -> Result<Wdl, TablebaseError> {
+        LocalTablebase::probe_wdl(self, board).ok_or(TablebaseError::NotCovered)
+    }
+}
@@ -0,0 +1,526 @@
+use crate::chengine::*;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+use std::ops::Neg;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+//cursed wins and blessed losses are decided by the fifty move rule
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Wdl {
+    Loss,
+    BlessedLoss,
+    Draw,
+    CursedWin,
+    Win,
+}
+
+impl Wdl {
+    pub fn from_value(value: i32) -> Option<Wdl> {
+        match value {
+            -2 => Some(Wdl::Loss),
+            -1 => Some(Wdl::BlessedLoss),
+            0 => Some(Wdl::Draw),
+            1 => Some(Wdl::CursedWin),
+            2 => Some(Wdl::Win),
+            _ => None,
+        }
+    }
+
+    pub fn value(self) -> i32 {
+        self as i32 - 2
+    }
+
+    pub fn signum(self) -> i32 {
+        self.value().signum()
+    }
+}
+
+impl Neg for Wdl {
+    type Output = Wdl;
+
+    fn neg(self) -> Wdl {
+        Wdl::from_value(-self.value()).unwrap()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TablebaseError {
+    //the position has too many pieces, castling rights or no table
+    NotCovered,
+    Request(String),
+    BadResponse(String),
+    NoMoves,
+    IllegalMove(String),
+}
+
+impl fmt::Display for TablebaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TablebaseError::NotCovered => write!(f, "position is not in the tablebase"),
+            TablebaseError::Request(err) => write!(f, "tablebase request failed: {}", err),
+            TablebaseError::BadResponse(err) => write!(f, "invalid tablebase response: {}", err),
+            TablebaseError::NoMoves => write!(f, "tablebase returned no moves"),
+            TablebaseError::IllegalMove(mv) => {
+                write!(f, "tablebase returned illegal move '{}'", mv)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TablebaseError {}
+
+//the result for the side to move and a legal move that keeps it, with the
+//distances in plies when the tablebase knows them
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProbeResult {
+    pub wdl: Wdl,
+    pub mv: Move,
+    //to the next capture or pawn move, which resets the fifty move rule
+    pub dtz: Option<i32>,
+    //to mate
+    pub dtm: Option<i32>,
+}
+
+impl ProbeResult {
+    pub fn new(wdl: Wdl, mv: Move) -> ProbeResult {
+        ProbeResult {
+            wdl,
+            mv,
+            dtz: None,
+            dtm: None,
+        }
+    }
+}
+
+//somewhere to look up endgames, searches fall back to searching on any error
+pub trait TablebaseProvider: Send {
+    fn probe(&mut self, board: &Board) -> Result<ProbeResult, TablebaseError>;
+
+    //the result alone, cheap enough to call inside the search
+    fn probe_wdl(&mut self, _board: &mut Board) -> Result<Wdl, TablebaseError> {
+        Err(TablebaseError::NotCovered)
+    }
+}
+
+pub struct NoTablebase;
+
+impl TablebaseProvider for NoTablebase {
+    fn probe(&mut self, _board: &Board) -> Result<ProbeResult, TablebaseError> {
+        Err(TablebaseError::NotCovered)
+    }
+}
+
+//the clocks are left out so transpositions find the same answer
+fn position_key(board: &Board) -> String {
+    board.fen().split(' ').take(4).collect::<Vec<_>>().join(" ")
+}
+
+//responses already seen, by position, optionally kept in a file with one
+//position and its response per line
+#[derive(Default)]
+pub struct ResponseCache {
+    responses: HashMap<String, String>,
+    file: Option<PathBuf>,
+}
+
+impl ResponseCache {
+    pub fn new() -> ResponseCache {
+        ResponseCache::default()
+    }
+
+    //reads whatever the file holds already, a missing file is an empty cache
+    pub fn open(path: &Path) -> ResponseCache {
+        let mut cache = ResponseCache {
+            responses: HashMap::new(),
+            file: Some(path.to_path_buf()),
+        };
+        if let Ok(text) = std::fs::read_to_string(path) {
+            for line in text.lines() {
+                if let Some((key, response)) = line.split_once('\t') {
+                    cache
+                        .responses
+                        .insert(key.to_string(), response.to_string());
+                }
+            }
+        }
+        cache
+    }
+
+    pub fn len(&self) -> usize {
+        self.responses.len()
+    }
+
+    pub fn get(&self, board: &Board) -> Option<&str> {
+        self.responses.get(&position_key(board)).map(|x| x.as_str())
+    }
+
+    //the response must be on one line, failing to write the file only loses
+    //it for later runs
+    pub fn insert(&mut self, board: &Board, response: &str) {
+        let key = position_key(board);
+        if let Some(path) = &self.file {
+            let _ = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut file| writeln!(file, "{}\t{}", key, response));
+        }
+        self.responses.insert(key, response.to_string());
+    }
+}
+
+//the lichess tablebase server, or anything answering in its format
+pub struct RemoteTablebase {
+    url: String,
+    timeout: Duration,
+    client: Option<reqwest::blocking::Client>,
+    cache: ResponseCache,
+}
+
+//where and how patiently to ask a tablebase server, kept by the front-ends so
+//the provider can be made again whenever one of them changes
+#[derive(Debug, Clone, PartialEq)]
+pub struct RemoteSettings {
+    pub url: String,
+    pub timeout: Duration,
+    //responses are kept here between runs when given
+    pub cache_file: Option<PathBuf>,
+}
+
+impl Default for RemoteSettings {
+    fn default() -> RemoteSettings {
+        RemoteSettings {
+            url: RemoteTablebase::LICHESS.to_string(),
+            timeout: RemoteTablebase::DEFAULT_TIMEOUT,
+            cache_file: None,
+        }
+    }
+}
+
+impl RemoteSettings {
+    pub fn connect(&self) -> RemoteTablebase {
+        let cache = match &self.cache_file {
+            Some(path) => ResponseCache::open(path),
+            None => ResponseCache::new(),
+        };
+        RemoteTablebase::with_url(&self.url)
+            .timeout(self.timeout)
+            .cache(cache)
+    }
+}
+
+//the tablebase options offered over uci and xboard, which share their names
+#[derive(Debug, Clone, Default)]
+pub struct TablebaseOptions {
+    pub online: bool,
+    pub remote: RemoteSettings,
+}
+
+impl TablebaseOptions {
+    //applies the option to the computer, returning a message for the gui, or
+    //None when the option is not one of these
+    pub fn set(&mut self, computer: &mut Computer, name: &str, value: &str) -> Option<String> {
+        let value = value.trim();
+        let path = match value {
+            "" | "<empty>" => None,
+            path => Some(PathBuf::from(path)),
+        };
+        let key = name.trim().to_ascii_lowercase();
+        match key.as_str() {
+            "syzygyprobelimit" => {
+                return Some(match value.parse::<u8>() {
+                    Ok(pieces) => {
+                        computer.tablebase_pieces = pieces.min(Computer::MAX_TABLEBASE_PIECES);
+                        format!("probing with up to {} pieces", computer.tablebase_pieces)
+                    }
+                    Err(..) => format!("invalid piece limit '{}'", value),
+                })
+            }
+            "onlinetablebase" => match value {
+                "true" | "1" => self.online = true,
+                "false" | "0" => self.online = false,
+                _ => return Some(format!("invalid switch '{}'", value)),
+            },
+            "onlinetablebaseurl" => self.remote.url = value.to_string(),
+            "onlinetablebasetimeout" => match value.parse::<u64>() {
+                Ok(millis) => self.remote.timeout = Duration::from_millis(millis.max(1)),
+                Err(..) => return Some(format!("invalid timeout '{}'", value)),
+            },
+            "onlinetablebasecache" => self.remote.cache_file = path,
+            _ => return None,
+        }
+        //settings changed while off are kept for later and leave any local tables
+        if !self.online && key != "onlinetablebase" {
+            return Some(format!("{} set to '{}'", name.trim(), value));
+        }
+        Some(self.apply(computer))
+    }
+
+    //turning the server off goes back to whatever local tables were loaded
+    fn apply(&self, computer: &mut Computer) -> String {
+        if !self.online {
+            computer.set_online_tablebase(None);
+            return "online tablebase off".to_string();
+        }
+        let remote = self.remote.connect();
+        let message = format!(
+            "online tablebase at {}, {} cached positions",
+            self.remote.url,
+            remote.cached()
+        );
+        computer.set_online_tablebase(Some(Box::new(remote)));
+        message
+    }
+}
+
+//how a position stands, from the side to move in it
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Category {
+    Win,
+    //maybe and syzygy results are only unsure about the fifty move rule
+    MaybeWin,
+    SyzygyWin,
+    CursedWin,
+    Draw,
+    BlessedLoss,
+    MaybeLoss,
+    SyzygyLoss,
+    Loss,
+    #[default]
+    #[serde(other)]
+    Unknown,
+}
+
+impl Category {
+    pub fn wdl(self) -> Option<Wdl> {
+        match self {
+            Category::Win | Category::MaybeWin | Category::SyzygyWin => Some(Wdl::Win),
+            Category::CursedWin => Some(Wdl::CursedWin),
+            Category::Draw => Some(Wdl::Draw),
+            Category::BlessedLoss => Some(Wdl::BlessedLoss),
+            Category::Loss | Category::MaybeLoss | Category::SyzygyLoss => Some(Wdl::Loss),
+            Category::Unknown => None,
+        }
+    }
+}
+
+//what the lichess server says about a position, distances are in plies and
+//negative when losing
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct TablebaseResponse {
+    #[serde(default)]
+    pub category: Category,
+    pub dtz: Option<i32>,
+    pub dtm: Option<i32>,
+    #[serde(default)]
+    pub checkmate: bool,
+    #[serde(default)]
+    pub stalemate: bool,
+    #[serde(default)]
+    pub insufficient_material: bool,
+    pub moves: Vec<TablebaseMove>,
+}
+
+//a move and how the position after it stands, from the opponent's side
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct TablebaseMove {
+    pub uci: String,
+    pub san: Option<String>,
+    #[serde(default)]
+    pub category: Category,
+    //older versions of the api sent -2 to 2 instead of a category
+    pub wdl: Option<i32>,
+    pub dtz: Option<i32>,
+    pub dtm: Option<i32>,
+    #[serde(default)]
+    pub zeroing: bool,
+    #[serde(default)]
+    pub checkmate: bool,
+    #[serde(default)]
+    pub stalemate: bool,
+}
+
+impl TablebaseMove {
+    //the result for the side making the move
+    pub fn wdl(&self) -> Option<Wdl> {
+        let after = self
+            .category
+            .wdl()
+            .or_else(|| self.wdl.and_then(Wdl::from_value))?;
+        Some(-after)
+    }
+
+    //higher is better among moves with the same result, mating at once, then
+    //winning fast or losing slowly, by distance to mate or to zeroing. A move
+    //without the distance is trusted least
+    fn rank(&self, wdl: Wdl, by_dtm: bool) -> i32 {
+        let distance = match by_dtm {
+            true => self.dtm,
+            false => self.dtz,
+        }
+        .map(|x| x.abs());
+        match wdl.signum() {
+            1 if self.checkmate => i32::MAX,
+            1 => -distance.unwrap_or(i32::MAX),
+            -1 => distance.unwrap_or(-1),
+            _ => 0,
+        }
+    }
+}
+
+impl TablebaseResponse {
+    //the move to play by our own ordering rather than the server's, ties going
+    //to whichever came first
+    pub fn best_move(&self) -> Option<(Wdl, &TablebaseMove)> {
+        let wdl = self.moves.iter().filter_map(TablebaseMove::wdl).max()?;
+        let candidates: Vec<&TablebaseMove> = self
+            .moves
+            .iter()
+            .filter(|mv| mv.wdl() == Some(wdl))
+            .collect();
+        //distances to mate and to zeroing can't be mixed, so mate is only used
+        //when every move has one
+        let by_dtm = candidates.iter().all(|mv| mv.dtm.is_some());
+        let mut best: Option<(i32, &TablebaseMove)> = None;
+        for mv in candidates {
+            let rank = mv.rank(wdl, by_dtm);
+            if best.is_none_or(|(best_rank, _)| rank > best_rank) {
+                best = Some((rank, mv));
+            }
+        }
+        best.map(|(_, mv)| (wdl, mv))
+    }
+}
+
+impl RemoteTablebase {
+    pub const LICHESS: &'static str = "http://tablebase.lichess.ovh/standard";
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+    #[allow(dead_code)]
+    pub fn new() -> RemoteTablebase {
+        Self::with_url(Self::LICHESS)
+    }
+
+    pub fn with_url(url: &str) -> RemoteTablebase {
+        RemoteTablebase {
+            url: url.to_string(),
+            timeout: Self::DEFAULT_TIMEOUT,
+            client: None,
+            cache: ResponseCache::new(),
+        }
+    }
+
+    //how long to wait for the whole response before searching instead
+    pub fn timeout(mut self, timeout: Duration) -> RemoteTablebase {
+        self.timeout = timeout;
+        self.client = None;
+        self
+    }
+
+    pub fn cache(mut self, cache: ResponseCache) -> RemoteTablebase {
+        self.cache = cache;
+        self
+    }
+
+    //positions that can be answered without asking the server
+    pub fn cached(&self) -> usize {
+        self.cache.len()
+    }
+
+    fn fetch(&mut self, board: &Board) -> Result<String, TablebaseError> {
+        let request_error = |err: reqwest::Error| TablebaseError::Request(err.to_string());
+        if self.client.is_none() {
+            let client = reqwest::blocking::Client::builder()
+                .timeout(self.timeout)
+                .build()
+                .map_err(request_error)?;
+            self.client = Some(client);
+        }
+        let client = self.client.as_ref().unwrap();
+        client
+            .get(&self.url)
+            .query(&[("fen", board.fen())])
+            .send()
+            .and_then(|response| response.error_for_status())
+            .and_then(|response| response.text())
+            .map_err(request_error)
+    }
+
+    //everything the server knows about the position, from the cache if it has
+    //been asked before
+    pub fn lookup(&mut self, board: &Board) -> Result<TablebaseResponse, TablebaseError> {
+        //the server has every ending the engine would ever probe
+        if board.piece_count > Computer::MAX_TABLEBASE_PIECES || board.has_castling_rights() {
+            return Err(TablebaseError::NotCovered);
+        }
+        let cached = self.cache.get(board).map(|text| text.to_string());
+        let text = match &cached {
+            Some(text) => text.clone(),
+            None => self.fetch(board)?,
+        };
+        let bad_response = |err: serde_json::Error| TablebaseError::BadResponse(err.to_string());
+        let value: serde_json::Value = serde_json::from_str(&text).map_err(bad_response)?;
+        let response = TablebaseResponse::deserialize(&value).map_err(bad_response)?;
+        //squashed onto one line for the cache file
+        if cached.is_none() {
+            self.cache.insert(board, &value.to_string());
+        }
+        Ok(response)
+    }
+}
+
+impl TablebaseProvider for RemoteTablebase {
+    fn probe(&mut self, board: &Board) -> Result<ProbeResult, TablebaseError> {
+        let response = self.lookup(board)?;
+        if response.moves.is_empty() {
+            return Err(TablebaseError::NoMoves);
+        }
+        let (wdl, best) = response
+            .best_move()
+            .ok_or_else(|| TablebaseError::BadResponse("no move has a known result".to_string()))?;
+        let mv = uci::parse_move(board, &best.uci)
+            .ok_or_else(|| TablebaseError::IllegalMove(best.uci.clone()))?;
+        Ok(ProbeResult {
+            wdl: response.category.wdl().unwrap_or(wdl),
+            mv,
+            dtz: response.dtz,
+            dtm: response.dtm,
+        })
+    }
+}
+
+//answers from positions given to it, for tests
+#[allow(dead_code)]
+#[derive(Default)]
+pub struct MockTablebase {
+    positions: HashMap<String, ProbeResult>,
+}
+
+#[allow(dead_code)]
+impl MockTablebase {
+    pub fn new() -> MockTablebase {
+        MockTablebase::default()
+    }
+
+    pub fn insert(&mut self, board: &Board, wdl: Wdl, mv: Move) {
+        self.positions
+            .insert(position_key(board), ProbeResult::new(wdl, mv));
+    }
+}
+
+impl TablebaseProvider for MockTablebase {
+    fn probe(&mut self, board: &Board) -> Result<ProbeResult, TablebaseError> {
+        self.positions
+            .get(&position_key(board))
+            .copied()
+            .ok_or(TablebaseError::NotCovered)
+    }
+
+    fn probe_wdl(&mut self, board: &mut Board) -> Result<Wdl, TablebaseError> {
+        self.probe(board).map(|result| result.wdl)
+    }
+}
@@ -2,79 +2,2005 @@ use crate::chengine::*;
 
 // #[test]
 // fn discovered_check() {
-//     let mut pieces = [[None; 8]; 8];
-//     pieces[0][3] = Some(Piece::new('k', Color::Black));
-//     pieces[4][3] = Some(Piece::new('q', Color::Black));
-//     pieces[6][3] = Some(Piece::new('b', Color::White));
-//     pieces[7][3] = Some(Piece::new('r', Color::White));
-//     pieces[7][4] = Some(Piece::new('k', Color::White));
-//     let board = Board::from(pieces, Square { x: 4, y: 7 }, Square { x: 3, y: 0 });
-//     assert_move_made(
-//         &board,
-//         Color::White,
-//         (Square { x: 3, y: 6 }, Square { x: 6, y: 3 }),
-//     );
+//     let board = BoardBuilder::new()
+//         .piece(sq("d1"), Piece::new(PieceKind::King, Color::Black))
+//         .piece(sq("d5"), Piece::new(PieceKind::Queen, Color::Black))
+//         .piece(sq("d7"), Piece::new(PieceKind::Bishop, Color::White))
+//         .piece(sq("d8"), Piece::new(PieceKind::Rook, Color::White))
+//         .piece(sq("e8"), Piece::new(PieceKind::King, Color::White))
+//         .build()
+//         .unwrap();
+//     assert_move_made(&board, Color::White, mv("d7", "g4"));
 // }
 
 // #[test]
 // fn fork() {
-//     let mut pieces = [[None; 8]; 8];
-//     pieces[0][1] = Some(Piece::new('k', Color::White));
-//     pieces[4][1] = Some(Piece::new('r', Color::White));
-//     pieces[3][4] = Some(Piece::new('n', Color::Black));
-//     pieces[5][5] = Some(Piece::new('k', Color::Black));
-//     let board = Board::from(pieces, Square { x: 1, y: 0 }, Square { x: 5, y: 5 });
-//     assert_move_made(
-//         &board,
-//         Color::Black,
-//         (Square { x: 4, y: 3 }, Square { x: 2, y: 2 }),
-//     );
-// }
-
-// #[test]
-// fn piece_count_from() {
-//     let mut pieces = [[None; 8]; 8];
-//     pieces[0][1] = Some(Piece::new('k', Color::White));
-//     pieces[4][1] = Some(Piece::new('r', Color::White));
-//     pieces[3][4] = Some(Piece::new('n', Color::Black));
-//     pieces[5][5] = Some(Piece::new('k', Color::Black));
-//     let board = Board::from(pieces, Square { x: 1, y: 0 }, Square { x: 5, y: 5 });
-//     assert_eq!(board.piece_count, 4);
+//     let board = BoardBuilder::new()
+//         .piece(sq("b1"), Piece::new(PieceKind::King, Color::White))
+//         .piece(sq("b5"), Piece::new(PieceKind::Rook, Color::White))
+//         .piece(sq("e4"), Piece::new(PieceKind::Knight, Color::Black))
+//         .piece(sq("f6"), Piece::new(PieceKind::King, Color::Black))
+//         .side_to_move(Color::Black)
+//         .build()
+//         .unwrap();
+//     assert_move_made(&board, Color::Black, mv("e4", "c3"));
 // }
 
 // #[test]
 // fn rook_endgame() {
-//     let mut pieces = [[None; 8]; 8];
-//     pieces[2][1] = Some(Piece::new('k', Color::White));
-//     pieces[7][7] = Some(Piece::new('r', Color::White));
-//     pieces[3][4] = Some(Piece::new('p', Color::Black));
-//     pieces[0][1] = Some(Piece::new('k', Color::Black));
-//     let board = Board::from(pieces, Square { x: 1, y: 2 }, Square { x: 1, y: 0 });
-//     assert_move_made(
-//         &board,
-//         Color::White,
-//         (Square { x: 7, y: 7 }, Square { x: 7, y: 0 }),
-//     );
+//     let board = BoardBuilder::new()
+//         .piece(sq("b3"), Piece::new(PieceKind::King, Color::White))
+//         .piece(sq("h8"), Piece::new(PieceKind::Rook, Color::White))
+//         .piece(sq("e4"), Piece::new(PieceKind::Pawn, Color::Black))
+//         .piece(sq("b1"), Piece::new(PieceKind::King, Color::Black))
+//         .build()
+//         .unwrap();
+//     assert_move_made(&board, Color::White, mv("h8", "h1"));
 // }
 
 // #[test]
 // fn board_promotion_test() {
-//     let mut pieces = [[None; 8]; 8];
-//     pieces[7][7] = Some(Piece::new('k', Color::Black));
-//     pieces[5][7] = Some(Piece::new('k', Color::White));
-//     pieces[1][2] = Some(Piece::new('p', Color::Black));
-//     pieces[1][1] = Some(Piece::new('p', Color::White));
-//     pieces[1][0] = Some(Piece::new('p', Color::White));
-//     let board = Board::from(pieces, Square { x: 7, y: 5 }, Square { x: 7, y: 7 });
-//     assert_move_made(
-//         &board,
-//         Color::Black,
-//         (Square { x: 2, y: 1 }, Square { x: 2, y: 0 }),
-//     );
+//     let board = BoardBuilder::new()
+//         .piece(sq("h8"), Piece::new(PieceKind::King, Color::Black))
+//         .piece(sq("h6"), Piece::new(PieceKind::King, Color::White))
+//         .piece(sq("c2"), Piece::new(PieceKind::Pawn, Color::Black))
+//         .piece(sq("b2"), Piece::new(PieceKind::Pawn, Color::White))
+//         .piece(sq("a2"), Piece::new(PieceKind::Pawn, Color::White))
+//         .side_to_move(Color::Black)
+//         .build()
+//         .unwrap();
+//     assert_move_made(&board, Color::Black, mv("c2", "c1"));
 // }
 
-fn assert_move_made(board: &Board, color: Color, expected: (Square, Square)) {
-    let mut computer = Computer::new(color, &OPENING_BOOK);
+#[test]
+fn builder_derives_kings_and_count() {
+    let board = BoardBuilder::new()
+        .piece(sq("b1"), Piece::new(PieceKind::King, Color::White))
+        .piece(sq("b5"), Piece::new(PieceKind::Rook, Color::White))
+        .piece(sq("e4"), Piece::new(PieceKind::Knight, Color::Black))
+        .piece(sq("f6"), Piece::new(PieceKind::King, Color::Black))
+        .side_to_move(Color::Black)
+        .build()
+        .unwrap();
+    assert_eq!(board.piece_count, 4);
+    assert_eq!(board.king_square(Color::White), sq("b1"));
+    assert_eq!(board.king_square(Color::Black), sq("f6"));
+    assert_eq!(board.side_to_move(), Color::Black);
+    assert_eq!(board.fen(), "8/8/5k2/1R6/4n3/8/8/1K6 b - - 0 1");
+}
+
+#[test]
+fn builder_matches_fen() {
+    let fen = "r3k2r/8/8/3pP3/8/8/8/R3K2R w Kq d6 0 12";
+    let mut builder = BoardBuilder::new();
+    for (square, piece) in [
+        ("a8", 'r'),
+        ("e8", 'k'),
+        ("h8", 'r'),
+        ("d5", 'p'),
+        ("e5", 'P'),
+        ("a1", 'R'),
+        ("e1", 'K'),
+        ("h1", 'R'),
+    ] {
+        builder = builder.piece(sq(square), Piece::from_char(piece).unwrap());
+    }
+    let board = builder
+        .castling(
+            Color::White,
+            CastleInfo {
+                kingside: true,
+                queenside: false,
+            },
+        )
+        .castling(
+            Color::Black,
+            CastleInfo {
+                kingside: false,
+                queenside: true,
+            },
+        )
+        .en_passant(sq("d6"))
+        .clocks(0, 12)
+        .build()
+        .unwrap();
+    assert_eq!(board.fen(), fen);
+    assert_eq!(board.hash(), Board::from_fen(fen).unwrap().hash());
+    assert_eq!(board.material(Color::White), 0.0);
+
+    //an uneven position starts out with its material difference
+    let mut board = Board::from_fen("4k3/8/8/8/8/8/8/3QK3 w - - 0 1").unwrap();
+    let queen = Piece::VALUE_QUEEN as f32;
+    assert_eq!(board.material(Color::White), queen);
+    assert_eq!(board.material(Color::Black), -queen);
+    let undo = board.exec_move(&mv("d1", "d8"));
+    assert_eq!(board.material(Color::White), queen);
+    board.unexec_move(&mv("d1", "d8"), undo);
+    assert_eq!(board.material(Color::White), queen);
+}
+
+#[test]
+fn builder_validation() {
+    let king = |color| Piece::new(PieceKind::King, color);
+    let kings = BoardBuilder::new()
+        .piece(sq("e1"), king(Color::White))
+        .piece(sq("e8"), king(Color::Black));
+    assert_eq!(
+        BoardBuilder::new()
+            .piece(sq("e1"), king(Color::White))
+            .build()
+            .err(),
+        Some(FenError::MissingKing(Color::Black))
+    );
+    assert_eq!(
+        kings
+            .clone()
+            .piece(sq("a1"), king(Color::White))
+            .build()
+            .err(),
+        Some(FenError::MultipleKings(Color::White))
+    );
+    assert_eq!(
+        kings
+            .clone()
+            .piece(sq("c8"), Piece::new(PieceKind::Pawn, Color::White))
+            .build()
+            .err(),
+        Some(FenError::PawnOnBackRank(sq("c8")))
+    );
+    assert_eq!(
+        kings
+            .clone()
+            .castling(Color::White, CastleInfo::both())
+            .build()
+            .err(),
+        Some(FenError::InconsistentCastlingRights('K'))
+    );
+    assert_eq!(
+        kings.clone().en_passant(sq("d6")).build().err(),
+        Some(FenError::BadEnPassant("d6".to_string()))
+    );
+    assert_eq!(
+        kings
+            .clone()
+            .piece(sq("e4"), Piece::new(PieceKind::Rook, Color::White))
+            .build()
+            .err(),
+        Some(FenError::OpponentInCheck)
+    );
+    assert!(kings.build().is_ok());
+}
+
+#[allow(dead_code)]
+fn assert_move_made(board: &Board, _color: Color, expected: Move) {
+    let mut computer = Computer::new(&OPENING_BOOK);
     computer.following_opening = false;
-    assert_eq!(computer.get_move(board, None, 6).1, expected);
+    assert_eq!(
+        computer
+            .get_move(board, &None, SearchLimit::Depth(6))
+            .unwrap()
+            .1,
+        expected
+    );
+}
+
+fn sq(name: &str) -> Square {
+    Square::new(name).unwrap()
+}
+
+fn mv(from: &str, to: &str) -> Move {
+    Move::new(sq(from), sq(to))
+}
+
+#[test]
+fn en_passant_square_set_by_double_push() {
+    let mut board = Board::new();
+    board.exec_move(&mv("e2", "e4"));
+    assert_eq!(board.en_passant(), Some(sq("e3")));
+    board.exec_move(&mv("g8", "f6"));
+    assert_eq!(board.en_passant(), None);
+}
+
+#[test]
+fn en_passant_capture() {
+    let mut board = Board::from_fen("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1").unwrap();
+    let before = board.fen();
+    assert!(board.get_moves(Color::White).contains(&mv("e5", "d6")));
+
+    let data = board.exec_move(&mv("e5", "d6"));
+    assert!(board.piece_at(&sq("d5")).is_none());
+    assert_eq!(board.piece_at(&sq("d6")).unwrap().kind, PieceKind::Pawn);
+    assert_eq!(board.piece_count, 3);
+
+    board.unexec_move(&mv("e5", "d6"), data);
+    assert_eq!(board.piece_at(&sq("d5")).unwrap().color, Color::Black);
+    assert!(board.piece_at(&sq("d6")).is_none());
+    assert_eq!(board.piece_count, 4);
+    assert_eq!(board.fen(), before);
+}
+
+#[test]
+fn en_passant_only_immediately() {
+    let board = Board::from_fen("4k3/8/8/3pP3/8/8/8/4K3 w - - 0 1").unwrap();
+    assert!(!board.get_moves(Color::White).contains(&mv("e5", "d6")));
+}
+
+#[test]
+fn en_passant_exposing_king() {
+    let board = Board::from_fen("8/8/8/r2pPK2/8/8/8/4k3 w - d6 0 1").unwrap();
+    assert!(!board.get_moves(Color::White).contains(&mv("e5", "d6")));
+}
+
+#[test]
+fn en_passant_fen_round_trip() {
+    let mut board = Board::new();
+    board.exec_move(&mv("e2", "e4"));
+    let fen = board.fen();
+    assert_eq!(
+        fen,
+        "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1"
+    );
+    assert_eq!(Board::from_fen(&fen).unwrap().fen(), fen);
+}
+
+#[test]
+fn promotion_choices() {
+    let board = Board::from_fen("4k3/1P6/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+    let promotions: Vec<_> = board
+        .get_moves(Color::White)
+        .into_iter()
+        .filter(|x| x.from == sq("b7"))
+        .map(|x| x.promotion)
+        .collect();
+    assert_eq!(promotions.len(), 4);
+    for id in Move::PROMOTIONS {
+        assert!(promotions.contains(&Some(id)));
+    }
+}
+
+#[test]
+fn underpromotion_exec_unexec() {
+    let mut board = Board::from_fen("r3k3/1P6/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+    let before = (board.fen(), board.eval(Color::White));
+    let promotion = Move::promote(sq("b7"), sq("a8"), PieceKind::Knight);
+    let data = board.exec_move(&promotion);
+    let knight = board.piece_at(&sq("a8")).unwrap();
+    assert_eq!(
+        (knight.kind, knight.color),
+        (PieceKind::Knight, Color::White)
+    );
+    assert!(board.piece_at(&sq("b7")).is_none());
+    assert_eq!(board.piece_count, 3);
+
+    board.unexec_move(&promotion, data);
+    assert_eq!(board.piece_at(&sq("b7")).unwrap().kind, PieceKind::Pawn);
+    assert_eq!(board.piece_at(&sq("a8")).unwrap().kind, PieceKind::Rook);
+    assert_eq!((board.fen(), board.eval(Color::White)), before);
+}
+
+#[test]
+fn knight_promotion_fork() {
+    let board = Board::from_fen("8/2P1k3/1q6/7p/7P/p7/P7/4K3 w - - 0 1").unwrap();
+    let mut computer = Computer::new(&OPENING_BOOK);
+    computer.following_opening = false;
+    assert_eq!(
+        computer
+            .get_move(&board, &None, SearchLimit::Depth(3))
+            .unwrap()
+            .1,
+        Move::promote(sq("c7"), sq("c8"), PieceKind::Knight)
+    );
+}
+
+#[test]
+fn fen_round_trip() {
+    for fen in [
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+        "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+        "4k3/8/8/8/4Pp2/8/8/4K3 b - e3 0 23",
+    ] {
+        assert_eq!(Board::from_fen(fen).unwrap().fen(), fen);
+    }
+    assert_eq!(
+        Board::from_fen("4k3/8/8/8/8/8/8/4K3_b_-_-_3_40")
+            .unwrap()
+            .fen(),
+        "4k3/8/8/8/8/8/8/4K3 b - - 3 40"
+    );
+}
+
+#[test]
+fn fen_clocks_and_side_to_move() {
+    let mut board = Board::new();
+    assert_eq!(board.side_to_move(), Color::White);
+    let knight = mv("g1", "f3");
+    board.exec_move(&knight);
+    assert_eq!(board.side_to_move(), Color::Black);
+    assert_eq!((board.halfmove_clock(), board.fullmove_number()), (1, 1));
+    board.exec_move(&mv("e7", "e5"));
+    assert_eq!((board.halfmove_clock(), board.fullmove_number()), (0, 2));
+    board.exec_move(&mv("f3", "e5"));
+    assert_eq!(
+        board.fen(),
+        "rnbqkbnr/pppp1ppp/8/4N3/8/8/PPPPPPPP/RNBQKB1R b KQkq - 0 2"
+    );
+
+    let mut board = Board::new();
+    let data = board.exec_move(&knight);
+    board.unexec_move(&knight, data);
+    assert_eq!(board.fen(), Board::new().fen());
+}
+
+#[test]
+fn fen_castling_rights() {
+    let board = Board::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w Kq - 0 1").unwrap();
+    let moves = board.get_moves(Color::White);
+    assert!(moves.contains(&mv("e1", "g1")));
+    assert!(!moves.contains(&mv("e1", "c1")));
+    let moves = board.get_moves(Color::Black);
+    assert!(moves.contains(&mv("e8", "c8")));
+    assert!(!moves.contains(&mv("e8", "g8")));
+}
+
+#[test]
+fn fen_errors() {
+    for (fen, error) in [
+        (
+            "rnbqkbnr/pppppppp/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            FenError::WrongRankCount(7),
+        ),
+        ("4k3/8/8/8/8/8/8/4K2 w - - 0 1", FenError::BadRankLength(1)),
+        (
+            "4k3/8/8/8/8/8/8/4K2R1 w - - 0 1",
+            FenError::BadRankLength(1),
+        ),
+        (
+            "4k3/8/8/8/8/8/8/4K2X w - - 0 1",
+            FenError::UnknownPiece('X'),
+        ),
+        (
+            "8/8/8/8/8/8/8/4K3 w - - 0 1",
+            FenError::MissingKing(Color::Black),
+        ),
+        (
+            "4k3/8/8/8/8/8/8/3KK3 w - - 0 1",
+            FenError::MultipleKings(Color::White),
+        ),
+        (
+            "4k2p/8/8/8/8/8/8/4K3 w - - 0 1",
+            FenError::PawnOnBackRank(sq("h8")),
+        ),
+        (
+            "4k3/8/8/8/8/8/8/4K3 x - - 0 1",
+            FenError::BadSideToMove("x".to_string()),
+        ),
+        (
+            "4k3/8/8/8/8/8/8/4K3 w Kx - 0 1",
+            FenError::BadCastlingRights("Kx".to_string()),
+        ),
+        (
+            "r3k2r/8/8/8/8/8/8/R3K2R w KKqq - 0 1",
+            FenError::BadCastlingRights("KKqq".to_string()),
+        ),
+        (
+            "4k3/8/8/8/8/8/8/4K3 w K - 0 1",
+            FenError::InconsistentCastlingRights('K'),
+        ),
+        (
+            "r3k3/8/8/8/8/8/8/4K3 w k - 0 1",
+            FenError::InconsistentCastlingRights('k'),
+        ),
+        (
+            "4k3/8/8/8/8/8/8/4K3 w - e6 0 1",
+            FenError::BadEnPassant("e6".to_string()),
+        ),
+        (
+            "4k3/8/8/8/8/8/8/4K3 w - - x 1",
+            FenError::BadClock("x".to_string()),
+        ),
+        (
+            "4k3/8/8/8/8/8/8/4K3 w - - 0 1 extra",
+            FenError::ExtraField("extra".to_string()),
+        ),
+        ("4k3/8/8/8/8/8/4R3/4K3 w - - 0 1", FenError::OpponentInCheck),
+        //squares past file h are not dropped
+        (
+            "rnbqkbnrp/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            FenError::BadRankLength(8),
+        ),
+        ("4k3/8/8/8/8/8/8p/4K3 w - - 0 1", FenError::BadRankLength(2)),
+        (
+            "4k3/8/8/8/8/8/71p/4K3 w - - 0 1",
+            FenError::BadRankLength(2),
+        ),
+        ("4k3/8/8/8/8/8/9/4K3 w - - 0 1", FenError::BadRankLength(2)),
+        ("8/8/8/8/8/8/8/K0k6 w - - 0 1", FenError::BadRankLength(1)),
+    ] {
+        assert_eq!(Board::from_fen(fen).err(), Some(error), "{}", fen);
+    }
+}
+
+#[test]
+fn outcome_checkmate() {
+    let board =
+        Board::from_fen("rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3").unwrap();
+    assert_eq!(board.outcome(), Outcome::Checkmate(Color::Black));
+    assert_eq!(board.eval(Color::Black), CHECKMATE);
+    assert_eq!(Board::new().outcome(), Outcome::Ongoing);
+}
+
+#[test]
+fn outcome_stalemate() {
+    let board = Board::from_fen("k7/8/1Q6/8/8/8/8/7K b - - 0 1").unwrap();
+    assert_eq!(board.outcome(), Outcome::Stalemate);
+    assert_eq!(board.eval(Color::White), DRAW);
+}
+
+#[test]
+fn outcome_fifty_moves() {
+    let board = Board::from_fen("4k3/8/8/8/8/8/8/R3K3 w - - 100 80").unwrap();
+    assert_eq!(board.outcome(), Outcome::Draw(DrawReason::FiftyMoves));
+}
+
+#[test]
+fn rook_promotion_avoids_stalemate() {
+    let board = Board::from_fen("8/6P1/8/1p2p2p/1P2P2P/8/8/k1K5 w - - 0 1").unwrap();
+    let mut computer = Computer::new(&OPENING_BOOK);
+    computer.following_opening = false;
+    assert_eq!(
+        computer
+            .get_move(&board, &None, SearchLimit::Depth(1))
+            .unwrap()
+            .1,
+        Move::promote(sq("g7"), sq("g8"), PieceKind::Rook)
+    );
+}
+
+#[test]
+fn threefold_repetition() {
+    let mut board = Board::new();
+    let shuffle = [
+        mv("g1", "f3"),
+        mv("g8", "f6"),
+        mv("f3", "g1"),
+        mv("f6", "g8"),
+    ];
+    for mv in shuffle.iter().chain(shuffle.iter()) {
+        assert!(!board.is_threefold_repetition());
+        board.exec_move(mv);
+    }
+    assert_eq!(board.repetitions(), 3);
+    assert_eq!(board.outcome(), Outcome::Draw(DrawReason::Repetition));
+
+    let data = board.exec_move(&mv("e2", "e4"));
+    assert_eq!(board.repetitions(), 1);
+    board.unexec_move(&mv("e2", "e4"), data);
+    assert!(board.is_threefold_repetition());
+}
+
+#[test]
+fn fifty_move_draw() {
+    let mut board = Board::from_fen("4k3/8/8/8/8/8/8/R3K3 w - - 99 80").unwrap();
+    assert!(!board.is_fifty_move_draw());
+    board.exec_move(&mv("a1", "a2"));
+    assert!(board.is_fifty_move_draw());
+    assert_eq!(board.outcome(), Outcome::Draw(DrawReason::FiftyMoves));
+}
+
+#[test]
+fn search_takes_repetition_when_behind() {
+    let mut board = Board::from_fen("3qk3/pp6/8/8/8/8/PP6/4K1N1 w - - 0 1").unwrap();
+    for mv in [
+        mv("g1", "f3"),
+        mv("d8", "d7"),
+        mv("f3", "g1"),
+        mv("d7", "d8"),
+    ] {
+        board.exec_move(&mv);
+    }
+    let mut computer = Computer::new(&OPENING_BOOK);
+    computer.following_opening = false;
+    assert_eq!(
+        computer
+            .get_move(&board, &None, SearchLimit::Depth(2))
+            .unwrap()
+            .1,
+        mv("g1", "f3")
+    );
+}
+
+#[test]
+fn insufficient_material() {
+    for (fen, dead) in [
+        ("4k3/8/8/8/8/8/8/4K3 w - - 0 1", true),
+        ("4k3/8/8/8/8/8/8/2B1K3 w - - 0 1", true),
+        ("4k3/8/8/8/8/8/8/1N2K3 w - - 0 1", true),
+        ("4kb2/8/8/8/8/8/8/2B1K3 w - - 0 1", true),
+        ("4k3/8/8/8/8/8/8/1B1BK3 w - - 0 1", true),
+        ("4kb2/8/8/8/8/8/8/3BK3 w - - 0 1", false),
+        ("4k3/8/8/8/8/8/8/1NN1K3 w - - 0 1", false),
+        ("4kn2/8/8/8/8/8/8/2B1K3 w - - 0 1", false),
+        ("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1", false),
+        ("4k3/8/8/8/8/8/8/R3K3 w - - 0 1", false),
+    ] {
+        let board = Board::from_fen(fen).unwrap();
+        assert_eq!(board.is_insufficient_material(), dead, "{}", fen);
+    }
+    let board = Board::from_fen("4k3/8/8/8/8/8/8/2B1K3 w - - 0 1").unwrap();
+    assert_eq!(
+        board.outcome(),
+        Outcome::Draw(DrawReason::InsufficientMaterial)
+    );
+    assert_eq!(board.eval(Color::White), DRAW);
+}
+
+fn assert_hash_consistent(board: &mut Board, depth: u8) {
+    assert_eq!(board.hash(), board.compute_hash(), "{}", board.fen());
+    if depth == 0 {
+        return;
+    }
+    for mv in board.get_moves(board.side_to_move()) {
+        let before = (board.hash(), board.fen());
+        let data = board.exec_move(&mv);
+        assert_hash_consistent(board, depth - 1);
+        board.unexec_move(&mv, data);
+        assert_eq!((board.hash(), board.fen()), before, "{}", mv);
+    }
+}
+
+#[test]
+fn zobrist_incremental() {
+    for fen in [
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+        "4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1",
+    ] {
+        assert_hash_consistent(&mut Board::from_fen(fen).unwrap(), 2);
+    }
+}
+
+#[test]
+fn zobrist_transposition() {
+    let mut a = Board::new();
+    for mv in [mv("g1", "f3"), mv("g8", "f6"), mv("b1", "c3")] {
+        a.exec_move(&mv);
+    }
+    let mut b = Board::new();
+    for mv in [mv("b1", "c3"), mv("g8", "f6"), mv("g1", "f3")] {
+        b.exec_move(&mv);
+    }
+    assert_eq!(a.hash(), b.hash());
+
+    //the en passant square is part of the key
+    let mut c = Board::new();
+    c.exec_move(&mv("e2", "e4"));
+    let d = Board::from_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1").unwrap();
+    assert_ne!(c.hash(), d.hash());
+}
+
+#[test]
+fn transposition_table_store_probe() {
+    let mut table = TranspositionTable::new(1);
+    let entry = |key, depth| TableEntry {
+        key,
+        depth,
+        bound: Bound::Exact,
+        score: depth as f32,
+        best: Some(mv("e2", "e4")),
+    };
+    assert!(table.probe(42).is_none());
+    table.store(entry(42, 3));
+    assert_eq!(table.probe(42).unwrap().depth, 3);
+
+    //a shallower result for the same position doesn't overwrite
+    table.store(entry(42, 1));
+    assert_eq!(table.probe(42).unwrap().depth, 3);
+
+    //a different position in the same slot does, and the old key misses
+    let colliding = 42 + (1 << 40);
+    table.store(entry(colliding, 1));
+    assert!(table.probe(42).is_none());
+    assert_eq!(table.probe(colliding).unwrap().best, Some(mv("e2", "e4")));
+
+    table.clear();
+    assert!(table.probe(colliding).is_none());
+}
+
+#[test]
+fn search_with_filled_table() {
+    let board = Board::from_fen("8/2P1k3/1q6/7p/7P/p7/P7/4K3 w - - 0 1").unwrap();
+    let mut computer = Computer::new(&OPENING_BOOK);
+    computer.following_opening = false;
+    let first = computer
+        .get_move(&board, &None, SearchLimit::Depth(3))
+        .unwrap();
+    //the second search starts from the stored best move and must agree with it
+    let second = computer
+        .get_move(&board, &None, SearchLimit::Depth(3))
+        .unwrap();
+    assert_eq!(first.1, second.1);
+    assert_eq!(first.0, second.0);
+}
+
+#[test]
+fn move_time_limit() {
+    let board =
+        Board::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")
+            .unwrap();
+    let mut computer = Computer::new(&OPENING_BOOK);
+    let now = std::time::Instant::now();
+    let (_, found) = computer
+        .search(
+            &board,
+            SearchLimit::MoveTime(std::time::Duration::from_millis(200)),
+        )
+        .unwrap();
+    assert!(now.elapsed() < std::time::Duration::from_secs(2));
+    assert!(computer.completed_depth() >= 1);
+    assert!(board.get_moves(Color::White).contains(&found));
+}
+
+#[test]
+fn node_limit() {
+    let board =
+        Board::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")
+            .unwrap();
+    let mut computer = Computer::new(&OPENING_BOOK);
+    let (_, found) = computer.search(&board, SearchLimit::Nodes(20_000)).unwrap();
+    assert!(computer.completed_depth() >= 1);
+    assert!(computer.completed_depth() < Computer::MAX_DEPTH);
+    assert!(board.get_moves(Color::White).contains(&found));
+}
+
+#[test]
+fn clock_limit() {
+    let board =
+        Board::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")
+            .unwrap();
+    let mut computer = Computer::new(&OPENING_BOOK);
+    let now = std::time::Instant::now();
+    let (_, found) = computer
+        .search(
+            &board,
+            SearchLimit::Clock {
+                remaining: std::time::Duration::from_secs(3),
+                increment: std::time::Duration::ZERO,
+                moves_to_go: Some(20),
+            },
+        )
+        .unwrap();
+    assert!(now.elapsed() < std::time::Duration::from_secs(2));
+    assert!(board.get_moves(Color::White).contains(&found));
+}
+
+#[test]
+fn mate_stops_deepening() {
+    let board = Board::from_fen("6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1").unwrap();
+    let mut computer = Computer::new(&OPENING_BOOK);
+    let (score, found) = computer.search(&board, SearchLimit::Depth(20)).unwrap();
+    assert_eq!(found, mv("a1", "a8"));
+    assert_eq!(score, CHECKMATE - 1.0);
+    assert!(computer.completed_depth() < 20);
+}
+
+#[test]
+fn table_mate_distance() {
+    let mut board = Board::from_fen("7k/8/5K2/8/8/8/8/R7 w - - 0 1").unwrap();
+    let mut computer = Computer::new(&OPENING_BOOK);
+    let (score, found) = computer.search(&board, SearchLimit::Depth(4)).unwrap();
+    assert_eq!(score, CHECKMATE - 3.0);
+
+    //the mate stored a ply further down is read back from the new root
+    board.exec_move(&found);
+    let (score, _) = computer.search(&board, SearchLimit::Depth(3)).unwrap();
+    assert_eq!(score, -CHECKMATE + 2.0);
+}
+
+#[test]
+fn table_skips_path_draws() {
+    //one move from the fifty move rule every quiet line is a draw
+    let board = Board::from_fen("8/8/8/4k3/8/8/8/KQ6 w - - 98 60").unwrap();
+    let mut computer = Computer::new(&OPENING_BOOK);
+    let (score, _) = computer.search(&board, SearchLimit::Depth(3)).unwrap();
+    assert_eq!(score, DRAW);
+
+    //which must not be remembered for the same position with a fresh clock
+    let board = Board::from_fen("8/8/8/4k3/8/8/8/KQ6 w - - 0 60").unwrap();
+    let (score, _) = computer.search(&board, SearchLimit::Depth(3)).unwrap();
+    let (fresh, _) = Computer::new(&OPENING_BOOK)
+        .search(&board, SearchLimit::Depth(3))
+        .unwrap();
+    assert!(score > DRAW);
+    assert_eq!(score, fresh);
+}
+
+#[test]
+fn search_without_moves() {
+    //checkmated and stalemated, there is nothing to search
+    for fen in [
+        "rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3",
+        "k7/2Q5/1K6/8/8/8/8/8 b - - 0 1",
+    ] {
+        let board = Board::from_fen(fen).unwrap();
+        let mut computer = Computer::new(&OPENING_BOOK);
+        computer.following_opening = false;
+        assert_eq!(
+            computer.get_move(&board, &None, SearchLimit::Depth(3)),
+            None
+        );
+        assert_eq!(computer.search(&board, SearchLimit::Depth(1)), None);
+    }
+}
+
+#[test]
+fn uci_position() {
+    let board = uci::parse_position("startpos moves e2e4 e7e5 g1f3").unwrap();
+    assert_eq!(
+        board.fen(),
+        "rnbqkbnr/pppp1ppp/8/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2"
+    );
+
+    let fen = "8/2P1k3/1q6/7p/7P/p7/P7/4K3 w - - 0 1";
+    let board = uci::parse_position(&format!("fen {} moves c7c8n", fen)).unwrap();
+    assert_eq!(board.piece_at(&sq("c8")).unwrap().kind, PieceKind::Knight);
+    assert!(uci::parse_position("startpos moves e2e5").is_none());
+    assert!(uci::parse_position("fen not a fen").is_none());
+
+    let mut protocol = uci::Uci::new();
+    assert!(protocol.handle("position startpos moves d2d4"));
+    assert_eq!(protocol.board().side_to_move(), Color::Black);
+    assert!(!protocol.handle("quit"));
+}
+
+#[test]
+fn uci_go() {
+    use std::time::Duration;
+    assert_eq!(
+        uci::parse_go("depth 4", Color::White),
+        SearchLimit::Depth(4)
+    );
+    assert_eq!(
+        uci::parse_go("nodes 1000", Color::White),
+        SearchLimit::Nodes(1000)
+    );
+    assert_eq!(
+        uci::parse_go("movetime 250", Color::Black),
+        SearchLimit::MoveTime(Duration::from_millis(250))
+    );
+    assert_eq!(
+        uci::parse_go("infinite", Color::White),
+        SearchLimit::Infinite
+    );
+    assert_eq!(
+        uci::parse_go("wtime 60000 btime 30000 winc 1000 binc 500", Color::Black),
+        SearchLimit::Clock {
+            remaining: Duration::from_secs(30),
+            increment: Duration::from_millis(500),
+            moves_to_go: None,
+        }
+    );
+}
+
+#[test]
+fn uci_move_strings() {
+    let board = Board::from_fen("8/2P1k3/1q6/7p/7P/p7/P7/4K3 w - - 0 1").unwrap();
+    let promotion = uci::parse_move(&board, "c7c8r").unwrap();
+    assert_eq!(
+        promotion,
+        Move::promote(sq("c7"), sq("c8"), PieceKind::Rook)
+    );
+    assert_eq!(promotion.to_uci(), "c7c8r");
+    //a promotion needs its piece
+    assert!(uci::parse_move(&board, "c7c8").is_none());
+    assert_eq!(mv("e1", "d2").to_uci(), "e1d2");
+
+    assert_eq!(uci::score_string(120.0), "cp 120");
+    assert_eq!(uci::score_string(CHECKMATE - 3.0), "mate 2");
+    assert_eq!(uci::score_string(-CHECKMATE + 2.0), "mate -1");
+}
+
+#[test]
+fn move_flags() {
+    let board = Board::from_fen("r3k2r/8/8/3pP3/8/8/P7/R3K2R w KQkq d6 0 1").unwrap();
+    let moves = board.get_moves(Color::White);
+    let find = |from: &str, to: &str| *moves.iter().find(|x| **x == mv(from, to)).unwrap();
+    assert!(find("a2", "a4").is_double_push());
+    assert!(!find("a2", "a3").is_double_push());
+    let ep = find("e5", "d6");
+    assert!(ep.is_en_passant() && ep.is_capture());
+    assert!(find("e1", "g1").is_castle());
+    assert!(find("e1", "c1").is_castle());
+    assert!(find("h1", "h8").is_capture());
+    assert_eq!(find("h1", "h7").flags, 0);
+
+    assert_eq!(
+        Move::from_uci("e7e8q"),
+        Some(Move::promote(sq("e7"), sq("e8"), PieceKind::Queen))
+    );
+    assert_eq!(Move::from_uci("e2e4"), Some(mv("e2", "e4")));
+    assert!(Move::from_uci("e2e4x").is_none());
+    //only pieces a pawn can become, and nothing after them
+    assert!(Move::from_uci("e7e8k").is_none());
+    assert!(Move::from_uci("e7e8p").is_none());
+    assert!(Move::from_uci("e7e8qq").is_none());
+    assert!(Move::from_uci("e2").is_none());
+}
+
+#[test]
+fn castling_rights() {
+    let fen = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1";
+    let rights = |board: &Board| {
+        let (white, black) = (
+            board.can_castle(Color::White),
+            board.can_castle(Color::Black),
+        );
+        (
+            white.kingside,
+            white.queenside,
+            black.kingside,
+            black.queenside,
+        )
+    };
+
+    //rooks lose their side's right from the square they leave
+    let mut board = Board::from_fen(fen).unwrap();
+    let undo = board.exec_move(&mv("h1", "g1"));
+    assert_eq!(rights(&board), (false, true, true, true));
+    board.unexec_move(&mv("h1", "g1"), undo);
+    assert_eq!(board.fen(), fen);
+    board.exec_move(&mv("a1", "b1"));
+    assert_eq!(rights(&board), (true, false, true, true));
+
+    //a black rook only touches black's rights
+    let mut board = Board::from_fen("r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1").unwrap();
+    board.exec_move(&mv("a8", "a5"));
+    assert_eq!(rights(&board), (true, true, true, false));
+    board.exec_move(&mv("h1", "h5"));
+    board.exec_move(&mv("h8", "h6"));
+    assert_eq!(rights(&board), (false, true, false, false));
+
+    //taking a rook at home removes the right it stood for
+    let mut board = Board::from_fen(fen).unwrap();
+    let undo = board.exec_move(&mv("a1", "a8"));
+    assert_eq!(rights(&board), (true, false, true, false));
+    board.unexec_move(&mv("a1", "a8"), undo);
+    assert_eq!(rights(&board), (true, true, true, true));
+    assert_eq!(board.fen(), fen);
+
+    //king moves give up both sides, castling included
+    let mut board = Board::from_fen(fen).unwrap();
+    board.exec_move(&mv("e1", "g1"));
+    assert_eq!(rights(&board), (false, false, true, true));
+    assert!(board.occupied(&sq("f1")) && !board.occupied(&sq("h1")));
+    board.exec_move(&mv("e8", "c8"));
+    assert_eq!(rights(&board), (false, false, false, false));
+    assert!(board.occupied(&sq("d8")) && !board.occupied(&sq("a8")));
+
+    //the hash follows the rights so transpositions with different rights differ
+    let mut moved = Board::from_fen(fen).unwrap();
+    for (from, to) in [("h1", "g1"), ("h8", "g8"), ("g1", "h1"), ("g8", "h8")] {
+        moved.exec_move(&mv(from, to));
+    }
+    assert_ne!(moved.hash(), Board::from_fen(fen).unwrap().hash());
+    assert_eq!(
+        moved.hash(),
+        Board::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w Qq - 4 3")
+            .unwrap()
+            .hash()
+    );
+}
+
+#[test]
+fn undo_info_restores_position() {
+    let mut board = Board::from_fen("r3k2r/8/8/3pP3/8/8/P7/R3K2R w KQkq d6 0 1").unwrap();
+    let fen = board.fen();
+    for mv in board.get_moves(Color::White) {
+        let undo = board.exec_move(&mv);
+        assert_eq!(undo.taken.is_some(), mv.is_capture());
+        assert_eq!(undo.en_passant, mv.is_en_passant());
+        assert_eq!(undo.castle != CastleMoveData::None, mv.is_castle());
+        board.unexec_move(&mv, undo);
+        assert_eq!(board.fen(), fen);
+    }
+}
+
+#[test]
+fn xboard_force_and_undo() {
+    let mut protocol = xboard::XBoard::new();
+    protocol.handle("new");
+    protocol.handle("force");
+    protocol.handle("usermove e2e4");
+    protocol.handle("usermove e7e5");
+    assert_eq!(protocol.board().side_to_move(), Color::White);
+    protocol.handle("undo");
+    assert_eq!(
+        protocol.board().fen(),
+        "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1"
+    );
+    protocol.handle("remove");
+    assert_eq!(protocol.board().fen(), Board::new().fen());
+    //illegal moves leave the board alone
+    protocol.handle("usermove e2e5");
+    assert_eq!(protocol.board().fen(), Board::new().fen());
+    assert!(!protocol.handle("quit"));
+}
+
+#[test]
+fn xboard_engine_replies() {
+    let mut protocol = xboard::XBoard::new();
+    protocol.handle("new");
+    protocol.handle("sd 2");
+    protocol.handle("usermove d2d4");
+    //the engine plays black after `new` and answers straight away
+    assert_eq!(protocol.board().side_to_move(), Color::White);
+
+    protocol.handle("setboard 6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1");
+    protocol.handle("go");
+    assert_eq!(
+        protocol.board().piece_at(&sq("a8")).unwrap().kind,
+        PieceKind::Rook
+    );
+    assert_eq!(protocol.board().outcome(), Outcome::Checkmate(Color::White));
+
+    assert_eq!(xboard::score_value(CHECKMATE - 1.0), 100001);
+    assert_eq!(xboard::score_value(-35.0), -35);
+}
+
+#[test]
+fn san_generation() {
+    let board = Board::new();
+    assert_eq!(san::to_san(&board, &mv("g1", "f3")), "Nf3");
+    assert_eq!(san::to_san(&board, &mv("e2", "e4")), "e4");
+
+    let kiwipete =
+        Board::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")
+            .unwrap();
+    assert_eq!(san::to_san(&kiwipete, &mv("e1", "g1")), "O-O");
+    assert_eq!(san::to_san(&kiwipete, &mv("e1", "c1")), "O-O-O");
+    assert_eq!(san::to_san(&kiwipete, &mv("e2", "a6")), "Bxa6");
+    assert_eq!(san::to_san(&kiwipete, &mv("g2", "h3")), "gxh3");
+    assert_eq!(san::to_san(&kiwipete, &mv("e5", "f7")), "Nxf7");
+
+    //knights on the same rank, rooks on the same file, and three queens
+    let board = Board::from_fen("1k6/8/8/R7/8/8/R7/N3N1K1 w - - 0 1").unwrap();
+    assert_eq!(san::to_san(&board, &mv("a1", "c2")), "Nac2");
+    assert_eq!(san::to_san(&board, &mv("a5", "a4")), "R5a4");
+    let board = Board::from_fen("8/7k/8/8/Q1Q5/8/Q7/6K1 w - - 0 1").unwrap();
+    assert_eq!(san::to_san(&board, &mv("a4", "b3")), "Qa4b3");
+
+    let board = Board::from_fen("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 2").unwrap();
+    assert_eq!(san::to_san(&board, &mv("e5", "d6")), "exd6");
+    let board = Board::from_fen("6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1").unwrap();
+    assert_eq!(san::to_san(&board, &mv("a1", "a8")), "Ra8#");
+    let board = Board::from_fen("8/2P1k3/1q6/7p/7P/p7/P7/4K3 w - - 0 1").unwrap();
+    assert_eq!(
+        san::to_san(
+            &board,
+            &Move::promote(sq("c7"), sq("c8"), PieceKind::Knight)
+        ),
+        "c8=N+"
+    );
+}
+
+#[test]
+fn san_parsing() {
+    let board = Board::new();
+    assert_eq!(san::parse_san(&board, "Nf3"), Some(mv("g1", "f3")));
+    assert_eq!(san::parse_san(&board, "e4"), Some(mv("e2", "e4")));
+    assert_eq!(san::parse_san(&board, "e5"), None);
+    assert_eq!(san::parse_san(&board, "Nd2"), None);
+
+    let board = Board::from_fen("1k6/8/8/R7/8/8/R7/N3N1K1 w - - 0 1").unwrap();
+    assert_eq!(san::parse_san(&board, "Nc2"), None);
+    assert_eq!(san::parse_san(&board, "Nec2"), Some(mv("e1", "c2")));
+    assert_eq!(san::parse_san(&board, "Ra2a3"), Some(mv("a2", "a3")));
+
+    let board = Board::from_fen("8/2P1k3/1q6/7p/7P/p7/P7/4K3 w - - 0 1").unwrap();
+    assert_eq!(
+        san::parse_san(&board, "c8=R"),
+        Some(Move::promote(sq("c7"), sq("c8"), PieceKind::Rook))
+    );
+    assert_eq!(san::parse_san(&board, "c8"), None);
+    for text in ["c8=q", "c8=b", "c8n"] {
+        assert_eq!(san::parse_san(&board, text), None, "{}", text);
+    }
+
+    //every legal move survives a round trip
+    let board =
+        Board::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")
+            .unwrap();
+    for legal in board.get_moves(Color::White) {
+        let text = san::to_san(&board, &legal);
+        assert_eq!(san::parse_san(&board, &text), Some(legal), "{}", text);
+    }
+    assert_eq!(san::parse_san(&board, "0-0-0"), Some(mv("e1", "c1")));
+}
+
+#[test]
+fn pgn_export() {
+    let mut game = pgn::Game::new();
+    game.set_tag("White", "chengine");
+    let mut board = Board::new();
+    for text in ["f3", "e5", "g4", "Qh4"] {
+        let mv = san::parse_san(&board, text).unwrap();
+        board.exec_move(&mv);
+        game.moves.push(mv);
+    }
+    game.comments.push((2, "a blunder".to_string()));
+    game.set_result(pgn::Game::result_of(board.outcome()));
+    assert_eq!(
+        game.to_pgn().unwrap(),
+        "[Event \"?\"]\n[Site \"?\"]\n[Date \"????.??.??\"]\n[Round \"?\"]\n[White \"chengine\"]\n\
+         [Black \"?\"]\n[Result \"0-1\"]\n\n1. f3 e5 {a blunder} 2. g4 Qh4# 0-1\n"
+    );
+
+    //a game that starts with black to move
+    let mut game = pgn::Game::new();
+    game.set_tag("FEN", "4k3/8/8/8/8/8/4P3/4K3 b - - 0 7");
+    game.moves.push(mv("e8", "d8"));
+    game.moves.push(mv("e2", "e4"));
+    assert!(game.to_pgn().unwrap().ends_with("\n7... Kd8 8. e4 *\n"));
+    assert!(game.to_pgn().unwrap().contains("[Result \"*\"]\n"));
+    game.moves.push(mv("e4", "e6"));
+    assert_eq!(
+        game.to_pgn(),
+        Err(pgn::PgnError::IllegalMove(2, "e4e6".to_string()))
+    );
+}
+
+#[test]
+fn pgn_import() {
+    let text = r#"[Event "First"]
+[White "A \"quoted\" name"]
+[Result "1-0"]
+
+1. e4 e5 {open game} 2. Nf3 (2. f4 exf4 {gambit (declined?)} (2... d5)) 2... Nc6 $1
+3.Bb5 ; the Spanish
+a6 1-0
+
+% an escaped line
+[Event "Second"]
+[SetUp "1"]
+[FEN "4k3/8/8/8/8/8/4P3/4K3 b - - 0 7"]
+
+7... Kd8 8. e4 *
+"#;
+    let games = pgn::Game::read(text).unwrap();
+    assert_eq!(games.len(), 2);
+
+    let first = &games[0];
+    assert_eq!(first.tag("Event"), Some("First"));
+    assert_eq!(first.tag("White"), Some("A \"quoted\" name"));
+    assert_eq!(first.result, "1-0");
+    assert_eq!(first.moves.len(), 6);
+    assert_eq!(first.moves[5], mv("a7", "a6"));
+    assert_eq!(
+        first.comments,
+        vec![(2, "open game".to_string()), (5, "the Spanish".to_string())]
+    );
+    //exporting and reading again gives the same game back
+    let again = pgn::Game::read(&first.to_pgn().unwrap()).unwrap();
+    assert_eq!(again, vec![first.clone()]);
+
+    let second = &games[1];
+    assert_eq!(second.result, "*");
+    assert_eq!(second.moves, vec![mv("e8", "d8"), mv("e2", "e4")]);
+    assert_eq!(
+        second.board().unwrap().fen(),
+        "3k4/8/8/8/4P3/8/8/4K3 b - e3 0 8"
+    );
+
+    //castling written with zeros is not a move number
+    let games = pgn::Game::read("1.e4 e5 2.Nf3 Nc6 3.Bc4 Bc5 4.0-0 Nf6 5.d3 0-0 *").unwrap();
+    assert_eq!(games[0].moves[6], mv("e1", "g1"));
+    assert_eq!(games[0].moves[9], mv("e8", "g8"));
+    let games =
+        pgn::Game::read("[FEN \"r3k3/8/8/8/8/8/8/4K3 b q - 0 1\"]\n\n1... 0-0-0 *").unwrap();
+    assert_eq!(games[0].moves, vec![mv("e8", "c8")]);
+
+    assert_eq!(
+        pgn::Game::read("1. e4 e4"),
+        Err(pgn::PgnError::IllegalMove(1, "e4".to_string()))
+    );
+    assert_eq!(
+        pgn::Game::read("1. e4 {never closed"),
+        Err(pgn::PgnError::Unterminated('{'))
+    );
+    assert_eq!(
+        pgn::Game::read("1. e4 (1. d4"),
+        Err(pgn::PgnError::Unterminated('('))
+    );
+}
+
+fn assert_perft(fen: &str, counts: &[u64]) {
+    let mut board = Board::from_fen(fen).unwrap();
+    for (depth, &count) in counts.iter().enumerate() {
+        assert_eq!(board.perft(depth as u8 + 1), count, "depth {}", depth + 1);
+    }
+    //perft has to leave the board as it found it
+    assert_eq!(board.fen(), fen);
+    assert_eq!(board.hash(), board.compute_hash());
+}
+
+#[test]
+fn perft_start_position() {
+    assert_perft(
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        &[20, 400, 8902, 197281],
+    );
+}
+
+#[test]
+fn perft_kiwipete() {
+    assert_perft(
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        &[48, 2039, 97862],
+    );
+}
+
+//pins, checks and en passant along the fourth rank
+#[test]
+fn perft_en_passant_pins() {
+    assert_perft(
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        &[14, 191, 2812, 43238],
+    );
+}
+
+//the fourth ply here needs a rook taken on its corner to lose its right
+#[test]
+fn perft_promotions() {
+    assert_perft(
+        "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+        &[6, 264, 9467, 422333],
+    );
+    assert_perft(
+        "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+        &[44, 1486, 62379],
+    );
+}
+
+#[test]
+fn perft_middlegame() {
+    assert_perft(
+        "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+        &[46, 2079, 89890],
+    );
+}
+
+//rooks on or next to their corners, so castling depends on which of them
+//has moved
+#[test]
+fn perft_castling_rights() {
+    assert_perft(
+        "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1",
+        &[26, 568, 13744, 314346],
+    );
+    assert_perft(
+        "r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1",
+        &[26, 568, 13744, 314346],
+    );
+    //and with one rook already gone from its corner
+    assert_perft(
+        "r3k2r/8/8/8/8/8/8/1R2K2R w Kkq - 0 1",
+        &[25, 567, 14095, 328965],
+    );
+    assert_perft(
+        "r3k2r/8/8/8/8/8/8/2R1K2R w Kkq - 0 1",
+        &[25, 548, 13502, 312835],
+    );
+    assert_perft(
+        "r3k2r/8/8/8/8/8/8/R3K1R1 w Qkq - 0 1",
+        &[25, 547, 13579, 316214],
+    );
+    assert_perft(
+        "1r2k2r/8/8/8/8/8/8/R3K2R w KQk - 0 1",
+        &[26, 583, 14252, 334705],
+    );
+    assert_perft(
+        "2r1k2r/8/8/8/8/8/8/R3K2R w KQk - 0 1",
+        &[25, 560, 13592, 317324],
+    );
+    assert_perft(
+        "r3k1r1/8/8/8/8/8/8/R3K2R w KQq - 0 1",
+        &[25, 560, 13607, 320792],
+    );
+}
+
+#[test]
+fn divide_sums_to_perft() {
+    let mut board = Board::new();
+    let divide = board.divide(3);
+    assert_eq!(divide.len(), 20);
+    assert_eq!(divide.iter().map(|x| x.1).sum::<u64>(), 8902);
+    assert!(divide.contains(&(mv("g1", "f3"), 440)));
+}
+
+#[test]
+fn bitboard_attacks() {
+    let bits = |names: &[&str]| {
+        names
+            .iter()
+            .fold(0, |bb, name| bb | bitboard::bit(&sq(name)))
+    };
+    let a1 = bitboard::index(&sq("a1"));
+    assert_eq!(bitboard::KNIGHT_ATTACKS[a1], bits(&["b3", "c2"]));
+    assert_eq!(bitboard::KING_ATTACKS[a1], bits(&["a2", "b1", "b2"]));
+    assert_eq!(
+        bitboard::PAWN_ATTACKS[bitboard::side(Color::Black)][bitboard::index(&sq("e5"))],
+        bits(&["d4", "f4"])
+    );
+    //rays stop on the first blocker, which is included
+    let d4 = bitboard::index(&sq("d4"));
+    let blockers = bits(&["d6", "b4", "f6"]);
+    assert_eq!(
+        bitboard::rook_attacks(d4, blockers),
+        bits(&["d5", "d6", "d3", "d2", "d1", "c4", "b4", "e4", "f4", "g4", "h4"])
+    );
+    assert_eq!(
+        bitboard::bishop_attacks(d4, blockers),
+        bits(&["e5", "f6", "c5", "b6", "a7", "c3", "b2", "a1", "e3", "f2", "g1"])
+    );
+    assert_eq!(
+        bitboard::squares(bits(&["h8", "a1", "c3"])).collect::<Vec<_>>(),
+        vec![sq("a1"), sq("c3"), sq("h8")]
+    );
+}
+
+#[test]
+fn bitboards_follow_moves() {
+    let mut board =
+        Board::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")
+            .unwrap();
+    let matches_mailbox = |board: &Board| {
+        (0..8).all(|x| {
+            (0..8).all(|y| {
+                let square = Square { x, y };
+                board.occupied(&square) == board.piece_at(&square).is_some()
+                    && [Color::White, Color::Black].into_iter().all(|color| {
+                        board.is_color(&square, color)
+                            == board.piece_at(&square).is_some_and(|p| p.color == color)
+                    })
+            })
+        })
+    };
+    for first in board.get_moves(Color::White) {
+        let first_data = board.exec_move(&first);
+        assert!(matches_mailbox(&board));
+        for second in board.get_moves(Color::Black) {
+            let second_data = board.exec_move(&second);
+            assert!(matches_mailbox(&board));
+            board.unexec_move(&second, second_data);
+        }
+        board.unexec_move(&first, first_data);
+        assert!(matches_mailbox(&board));
+    }
+}
+
+#[test]
+fn piece_kind_conversions() {
+    for kind in PieceKind::ALL {
+        assert_eq!(PieceKind::from_char(kind.to_char()), Some(kind));
+        assert_eq!(
+            PieceKind::from_char(kind.to_char().to_ascii_uppercase()),
+            Some(kind)
+        );
+        for color in [Color::White, Color::Black] {
+            assert_eq!(
+                PieceKind::from_glyph(kind.glyph(color)),
+                Some((kind, color))
+            );
+            let piece = Piece::new(kind, color);
+            let parsed = Piece::from_char(piece.to_char()).unwrap();
+            assert_eq!((parsed.kind, parsed.color), (kind, color));
+        }
+    }
+    assert_eq!(PieceKind::Knight.glyph(Color::Black), '♞');
+    assert_eq!(Piece::new(PieceKind::Queen, Color::White).to_char(), 'Q');
+    assert_eq!(PieceKind::from_char('x'), None);
+    assert_eq!(PieceKind::from_glyph('x'), None);
+    assert!(Piece::from_char('1').is_none());
+}
+
+//a directory of tablebase files for the test, with junk in place of real tables
+fn fake_tablebase_dir(test: &str, files: &[&str]) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("chengine-{}-{}", test, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    for file in files {
+        std::fs::write(dir.join(file), [0u8; 80]).unwrap();
+    }
+    dir
+}
+
+#[test]
+fn syzygy_discovery() {
+    let dir = fake_tablebase_dir(
+        "discovery",
+        &[
+            "KQvK.rtbw",
+            "KQvK.rtbz",
+            "KRPvKR.rtbw",
+            "KvKQ.rtbw",
+            "KQ.rtbw",
+            "notes.txt",
+        ],
+    );
+    let tablebase = syzygy::LocalTablebase::open(dir.to_str().unwrap());
+    assert_eq!(tablebase.len(), 3);
+    assert_eq!(tablebase.max_pieces(), 5);
+    assert_eq!(syzygy::LocalTablebase::open("/no/such/dir").len(), 0);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn syzygy_without_tables() {
+    let dir = fake_tablebase_dir("without", &["KQvK.rtbw"]);
+    let mut tablebase = syzygy::LocalTablebase::open(dir.to_str().unwrap());
+
+    //bare kings and forced captures into them need no file
+    let mut board = Board::from_fen("8/8/4k3/8/8/3K4/8/8 w - - 0 1").unwrap();
+    assert_eq!(tablebase.probe_wdl(&mut board), Some(Wdl::Draw));
+    let mut board = Board::from_fen("k7/1Q6/8/8/8/8/8/7K b - - 0 1").unwrap();
+    assert_eq!(tablebase.probe_wdl(&mut board), Some(Wdl::Draw));
+    assert_eq!(tablebase.probe_dtz(&mut board), Some(0));
+    assert_eq!(board.fen(), "k7/1Q6/8/8/8/8/8/7K b - - 0 1");
+
+    //a broken file is no answer, and neither is material beyond the tables
+    let mut start = Board::new();
+    assert_eq!(tablebase.probe_wdl(&mut start), None);
+    let mut board = Board::from_fen("k7/8/1QK5/8/8/8/8/8 w - - 0 1").unwrap();
+    assert_eq!(tablebase.probe_wdl(&mut board), None);
+    assert_eq!(tablebase.best_move(&board), None);
+
+    //so the engine searches instead
+    let mut computer = Computer::new(&OPENING_BOOK);
+    computer.following_opening = false;
+    assert_eq!(computer.set_syzygy_path(dir.to_str().unwrap()), 1);
+    let (score, best) = computer
+        .get_move(&board, &None, SearchLimit::Depth(3))
+        .unwrap();
+    assert_eq!((score, best), (CHECKMATE - 1.0, mv("b6", "b7")));
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+//KQvK and KPvK in the syzygy format, with white to move stored in the dtz tables
+const SYZYGY_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/syzygy");
+
+#[test]
+fn syzygy_tables() {
+    let mut tablebase = syzygy::LocalTablebase::open(SYZYGY_DIR);
+    assert_eq!(tablebase.len(), 2);
+    let mut probe = |fen| {
+        let mut board = Board::from_fen(fen).unwrap();
+        let wdl = tablebase.probe_wdl(&mut board);
+        (wdl, tablebase.probe_dtz(&mut board))
+    };
+
+    assert_eq!(
+        probe("k7/8/1QK5/8/8/8/8/8 w - - 0 1"),
+        (Some(Wdl::Win), Some(1))
+    );
+    assert_eq!(
+        probe("8/8/8/8/4k3/8/1Q6/K7 b - - 0 1"),
+        (Some(Wdl::Loss), Some(-20))
+    );
+    //every piece on the long diagonal, and two of them with the third off it
+    assert_eq!(
+        probe("7k/8/8/8/8/8/1K6/Q7 w - - 0 1"),
+        (Some(Wdl::Win), Some(11))
+    );
+    assert_eq!(
+        probe("8/8/8/8/4k3/8/6K1/7Q w - - 0 1"),
+        (Some(Wdl::Win), Some(15))
+    );
+    assert_eq!(
+        probe("8/8/8/5k2/8/8/1Q6/K7 w - - 0 1"),
+        (Some(Wdl::Win), Some(19))
+    );
+    //black to move is only in the wdl table, so dtz looks a ply ahead
+    assert_eq!(
+        probe("8/8/8/8/3k4/2Q5/1K6/8 b - - 0 1"),
+        (Some(Wdl::Loss), Some(-14))
+    );
+    assert_eq!(
+        probe("kQK5/8/8/8/8/8/8/8 b - - 0 1"),
+        (Some(Wdl::Loss), Some(-1))
+    );
+    assert_eq!(
+        probe("k7/1Q6/8/8/8/8/8/7K b - - 0 1"),
+        (Some(Wdl::Draw), Some(0))
+    );
+    //with the colours swapped the table is read flipped
+    assert_eq!(
+        probe("q7/1k6/8/8/8/8/8/7K b - - 0 1"),
+        (Some(Wdl::Win), Some(11))
+    );
+    assert_eq!(
+        probe("8/1k6/2q5/3K4/8/8/8/8 w - - 0 1"),
+        (Some(Wdl::Loss), Some(-14))
+    );
+
+    let board = Board::from_fen("k7/8/1QK5/8/8/8/8/8 w - - 0 1").unwrap();
+    assert_eq!(
+        tablebase.best_move(&board),
+        Some((Wdl::Win, mv("b6", "b7")))
+    );
+}
+
+#[test]
+fn syzygy_pawn_tables() {
+    let mut tablebase = syzygy::LocalTablebase::open(SYZYGY_DIR);
+    let mut probe = |fen| {
+        let mut board = Board::from_fen(fen).unwrap();
+        let wdl = tablebase.probe_wdl(&mut board);
+        (wdl, tablebase.probe_dtz(&mut board))
+    };
+
+    assert_eq!(
+        probe("8/8/8/k7/8/8/K4P2/8 w - - 0 1"),
+        (Some(Wdl::Win), Some(19))
+    );
+    assert_eq!(
+        probe("4k3/8/4K3/4P3/8/8/8/8 w - - 0 1"),
+        (Some(Wdl::Win), Some(3))
+    );
+    assert_eq!(
+        probe("8/8/8/8/k3P3/8/8/K7 w - - 0 1"),
+        (Some(Wdl::Draw), Some(0))
+    );
+    //pawns on the h file are read mirrored from the a file's table
+    assert_eq!(
+        probe("8/8/8/8/2k5/4K3/7P/8 w - - 0 1"),
+        (Some(Wdl::Win), Some(5))
+    );
+    //black to move is only in the wdl table, so dtz looks a ply ahead
+    assert_eq!(
+        probe("4k3/8/4K3/4P3/8/8/8/8 b - - 0 1"),
+        (Some(Wdl::Loss), Some(-4))
+    );
+    assert_eq!(
+        probe("3k4/8/3K4/4P3/8/8/8/8 b - - 0 1"),
+        (Some(Wdl::Loss), Some(-6))
+    );
+    assert_eq!(
+        probe("8/8/8/8/8/8/5P2/K3k3 b - - 0 1"),
+        (Some(Wdl::Draw), Some(0))
+    );
+    //with the colours swapped the table is read flipped
+    assert_eq!(
+        probe("8/k4p2/8/8/K7/8/8/8 b - - 0 1"),
+        (Some(Wdl::Win), Some(19))
+    );
+    assert_eq!(
+        probe("8/8/8/8/4p3/4k3/8/4K3 w - - 0 1"),
+        (Some(Wdl::Loss), Some(-4))
+    );
+
+    let board = Board::from_fen("3k4/8/3K4/4P3/8/8/8/8 w - - 0 1").unwrap();
+    assert_eq!(
+        tablebase.best_move(&board),
+        Some((Wdl::Win, mv("e5", "e6")))
+    );
+}
+
+#[test]
+fn wdl_order() {
+    assert!(Wdl::Win > Wdl::CursedWin && Wdl::CursedWin > Wdl::Draw);
+    assert!(Wdl::Draw > Wdl::BlessedLoss && Wdl::BlessedLoss > Wdl::Loss);
+    assert_eq!(-Wdl::Win, Wdl::Loss);
+    assert_eq!(-Wdl::CursedWin, Wdl::BlessedLoss);
+    assert_eq!(-Wdl::Draw, Wdl::Draw);
+    assert_eq!(Wdl::Loss.value(), -2);
+}
+
+//answers each request to 127.0.0.1 with the next canned status and body, the
+//handle gives back the request lines it saw
+fn stub_server(
+    responses: Vec<(u16, &'static str)>,
+) -> (String, std::thread::JoinHandle<Vec<String>>) {
+    use std::io::{BufRead, BufReader, Write};
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/standard", listener.local_addr().unwrap());
+    let handle = std::thread::spawn(move || {
+        let mut requests = Vec::new();
+        for (status, body) in responses {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            requests.push(line.trim().to_string());
+            //the rest of the headers, up to the blank line
+            loop {
+                let mut header = String::new();
+                if reader.read_line(&mut header).unwrap() == 0 || header.trim().is_empty() {
+                    break;
+                }
+            }
+            write!(
+                stream,
+                "HTTP/1.1 {} Stub\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            )
+            .unwrap();
+        }
+        requests
+    });
+    (url, handle)
+}
+
+#[test]
+fn mock_tablebase() {
+    let board = Board::from_fen("k7/8/1QK5/8/8/8/8/8 w - - 0 1").unwrap();
+    let mut mock = MockTablebase::new();
+    mock.insert(&board, Wdl::Win, mv("c6", "c7"));
+    assert_eq!(
+        mock.probe(&board),
+        Ok(ProbeResult::new(Wdl::Win, mv("c6", "c7")))
+    );
+    assert_eq!(mock.probe(&Board::new()), Err(TablebaseError::NotCovered));
+    assert_eq!(NoTablebase.probe(&board), Err(TablebaseError::NotCovered));
+
+    //the tablebase move is played without searching
+    let mut computer = Computer::new(&OPENING_BOOK);
+    computer.following_opening = false;
+    computer.set_tablebase(Box::new(mock));
+    let (score, best) = computer
+        .get_move(&board, &None, SearchLimit::Depth(3))
+        .unwrap();
+    assert_eq!((score, best), (TABLEBASE_WIN, mv("c6", "c7")));
+    assert_eq!(computer.nodes(), 0);
+
+    //but an illegal one is ignored
+    let mut mock = MockTablebase::new();
+    mock.insert(&board, Wdl::Win, mv("a1", "a2"));
+    computer.set_tablebase(Box::new(mock));
+    let (score, best) = computer
+        .get_move(&board, &None, SearchLimit::Depth(3))
+        .unwrap();
+    assert_eq!((score, best), (CHECKMATE - 1.0, mv("b6", "b7")));
+}
+
+#[test]
+fn remote_tablebase() {
+    let board = Board::from_fen("k7/8/1QK5/8/8/8/8/8 w - - 0 1").unwrap();
+    let (url, server) = stub_server(vec![
+        (
+            200,
+            r#"{"category":"win","moves":[{"uci":"b6b7","category":"loss"}]}"#,
+        ),
+        (200, r#"{"category":"win","moves":[]}"#),
+        (
+            200,
+            r#"{"category":"win","moves":[{"uci":"a1a2","category":"loss"}]}"#,
+        ),
+        (
+            200,
+            r#"{"category":"win","moves":[{"uci":"b6b7","category":"sideways"}]}"#,
+        ),
+        (200, "<html>not json</html>"),
+        (500, "{}"),
+    ]);
+    //a fresh provider each time, so the cache does not answer
+    let probe = |board: &Board| RemoteTablebase::with_url(&url).probe(board);
+    assert_eq!(
+        probe(&board),
+        Ok(ProbeResult::new(Wdl::Win, mv("b6", "b7")))
+    );
+    assert_eq!(probe(&board), Err(TablebaseError::NoMoves));
+    assert_eq!(
+        probe(&board),
+        Err(TablebaseError::IllegalMove("a1a2".to_string()))
+    );
+    assert!(matches!(probe(&board), Err(TablebaseError::BadResponse(_))));
+    assert!(matches!(probe(&board), Err(TablebaseError::BadResponse(_))));
+    assert!(matches!(probe(&board), Err(TablebaseError::Request(_))));
+    //too much material is never sent
+    assert_eq!(probe(&Board::new()), Err(TablebaseError::NotCovered));
+
+    let requests = server.join().unwrap();
+    assert_eq!(requests.len(), 6);
+    assert!(requests[0].starts_with("GET /standard?fen=k7%2F8%2F1QK5%2F8%2F8%2F8%2F8%2F8+w+"));
+}
+
+#[test]
+fn remote_tablebase_fallback() {
+    let board = Board::from_fen("k7/8/1QK5/8/8/8/8/8 w - - 0 1").unwrap();
+    let mut computer = Computer::new(&OPENING_BOOK);
+    computer.following_opening = false;
+
+    let (url, server) = stub_server(vec![(200, r#"{"category":"win","moves":[]}"#)]);
+    computer.set_tablebase(Box::new(RemoteTablebase::with_url(&url)));
+    let (score, best) = computer
+        .get_move(&board, &None, SearchLimit::Depth(3))
+        .unwrap();
+    assert_eq!((score, best), (CHECKMATE - 1.0, mv("b6", "b7")));
+    assert_eq!(server.join().unwrap().len(), 1);
+
+    //nothing listening at all
+    let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/standard", closed.local_addr().unwrap());
+    drop(closed);
+    computer.set_tablebase(Box::new(RemoteTablebase::with_url(&url)));
+    let (score, best) = computer
+        .get_move(&board, &None, SearchLimit::Depth(3))
+        .unwrap();
+    assert_eq!((score, best), (CHECKMATE - 1.0, mv("b6", "b7")));
+}
+
+#[test]
+fn remote_tablebase_cache() {
+    let board = Board::from_fen("k7/8/1QK5/8/8/8/8/8 w - - 0 1").unwrap();
+    let dir = fake_tablebase_dir("cache", &[]);
+    let path = dir.join("responses.txt");
+    let (url, server) = stub_server(vec![
+        (
+            200,
+            r#"{"category":"win","moves":[{"uci":"b6b7","category":"loss"}]}"#,
+        ),
+        (200, "not json"),
+    ]);
+    let mut remote = RemoteTablebase::with_url(&url).cache(ResponseCache::open(&path));
+    assert_eq!(
+        remote.probe(&board),
+        Ok(ProbeResult::new(Wdl::Win, mv("b6", "b7")))
+    );
+    //the same position later in the game is not asked for again
+    let later = Board::from_fen("k7/8/1QK5/8/8/8/8/8 w - - 12 40").unwrap();
+    assert_eq!(
+        remote.probe(&later),
+        Ok(ProbeResult::new(Wdl::Win, mv("b6", "b7")))
+    );
+    //and nonsense is not kept
+    let other = Board::from_fen("k7/8/2K5/1Q6/8/8/8/8 w - - 0 1").unwrap();
+    assert!(remote.probe(&other).is_err());
+    assert_eq!(server.join().unwrap().len(), 2);
+
+    //the file answers for a server that is not there
+    let cache = ResponseCache::open(&path);
+    assert_eq!(cache.len(), 1);
+    let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/standard", closed.local_addr().unwrap());
+    drop(closed);
+    let mut remote = RemoteTablebase::with_url(&url).cache(cache);
+    assert_eq!(
+        remote.probe(&board),
+        Ok(ProbeResult::new(Wdl::Win, mv("b6", "b7")))
+    );
+    assert!(matches!(
+        remote.probe(&other),
+        Err(TablebaseError::Request(_))
+    ));
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn remote_tablebase_timeout() {
+    let board = Board::from_fen("k7/8/1QK5/8/8/8/8/8 w - - 0 1").unwrap();
+    //accepts the connection but never answers
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/standard", listener.local_addr().unwrap());
+    let server = std::thread::spawn(move || {
+        let connection = listener.accept().unwrap();
+        std::thread::sleep(std::time::Duration::from_secs(2));
+        drop(connection);
+    });
+    let mut remote = RemoteTablebase::with_url(&url).timeout(std::time::Duration::from_millis(200));
+    let start = std::time::Instant::now();
+    assert!(matches!(
+        remote.probe(&board),
+        Err(TablebaseError::Request(_))
+    ));
+    assert!(start.elapsed() < std::time::Duration::from_secs(1));
+    server.join().unwrap();
+}
+
+#[test]
+fn tablebase_options() {
+    let board = Board::from_fen("k7/8/1QK5/8/8/8/8/8 w - - 0 1").unwrap();
+    let dir = fake_tablebase_dir("options", &[]);
+    let path = dir.join("responses.txt");
+    ResponseCache::open(&path).insert(
+        &board,
+        r#"{"category":"win","moves":[{"uci":"b6b7","category":"loss"}]}"#,
+    );
+    let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/standard", closed.local_addr().unwrap());
+    drop(closed);
+
+    let mut computer = Computer::new(&OPENING_BOOK);
+    computer.following_opening = false;
+    let mut options = TablebaseOptions::default();
+    let mut set = |computer: &mut Computer, name, value| options.set(computer, name, value);
+    assert_eq!(set(&mut computer, "Hash", "16"), None);
+    assert!(set(&mut computer, "OnlineTablebaseTimeout", "soon")
+        .unwrap()
+        .starts_with("invalid"));
+    //nothing is asked for until the server is switched on
+    set(&mut computer, "OnlineTablebaseUrl", &url);
+    set(&mut computer, "OnlineTablebaseTimeout", "200");
+    set(
+        &mut computer,
+        "OnlineTablebaseCache",
+        path.to_str().unwrap(),
+    );
+    assert_eq!(
+        set(&mut computer, "onlinetablebase", "true"),
+        Some(format!("online tablebase at {}, 1 cached positions", url))
+    );
+    let (score, best) = computer
+        .get_move(&board, &None, SearchLimit::Depth(3))
+        .unwrap();
+    assert_eq!((score, best), (TABLEBASE_WIN, mv("b6", "b7")));
+
+    //too few pieces allowed and the search finds the mate itself
+    set(&mut computer, "SyzygyProbeLimit", "2");
+    assert_eq!(computer.tablebase_pieces, 2);
+    let (score, best) = computer
+        .get_move(&board, &None, SearchLimit::Depth(3))
+        .unwrap();
+    assert_eq!((score, best), (CHECKMATE - 1.0, mv("b6", "b7")));
+    set(&mut computer, "SyzygyProbeLimit", "32");
+    assert_eq!(computer.tablebase_pieces, Computer::MAX_TABLEBASE_PIECES);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn online_tablebase_keeps_local() {
+    let board = Board::from_fen("8/8/8/5k2/8/8/1Q6/K7 w - - 0 1").unwrap();
+    let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/standard", closed.local_addr().unwrap());
+    drop(closed);
+    let mut computer = Computer::new(&OPENING_BOOK);
+    computer.following_opening = false;
+    let mut options = TablebaseOptions::default();
+    let score = |computer: &mut Computer| {
+        computer
+            .get_move(&board, &None, SearchLimit::Depth(3))
+            .unwrap()
+            .0
+    };
+
+    //guis switch the server off at startup, after or before the tables are set
+    assert_eq!(computer.set_syzygy_path(SYZYGY_DIR), 2);
+    options.set(&mut computer, "OnlineTablebase", "false");
+    assert_eq!(score(&mut computer), TABLEBASE_WIN);
+
+    //a server that cannot be reached means searching, until it is off again
+    options.set(&mut computer, "OnlineTablebaseUrl", &url);
+    options.set(&mut computer, "OnlineTablebaseTimeout", "200");
+    options.set(&mut computer, "OnlineTablebase", "true");
+    assert!(score(&mut computer) < TABLEBASE_WIN);
+    options.set(&mut computer, "OnlineTablebase", "false");
+    assert_eq!(score(&mut computer), TABLEBASE_WIN);
+
+    //tables set while online wait behind the server
+    options.set(&mut computer, "OnlineTablebase", "true");
+    computer.set_syzygy_path("/no/such/dir");
+    options.set(&mut computer, "OnlineTablebase", "false");
+    assert!(score(&mut computer) < TABLEBASE_WIN);
+}
+
+#[test]
+fn tablebase_piece_threshold() {
+    let board = Board::from_fen("k7/8/1QK5/8/8/8/8/8 w - - 0 1").unwrap();
+    let mut mock = MockTablebase::new();
+    mock.insert(&board, Wdl::Win, mv("c6", "c7"));
+    let mut computer = Computer::new(&OPENING_BOOK);
+    computer.following_opening = false;
+    computer.set_tablebase(Box::new(mock));
+    computer.tablebase_pieces = 2;
+    let (score, best) = computer
+        .get_move(&board, &None, SearchLimit::Depth(3))
+        .unwrap();
+    assert_eq!((score, best), (CHECKMATE - 1.0, mv("b6", "b7")));
+    computer.tablebase_pieces = 3;
+    let (score, best) = computer
+        .get_move(&board, &None, SearchLimit::Depth(3))
+        .unwrap();
+    assert_eq!((score, best), (TABLEBASE_WIN, mv("c6", "c7")));
+}
+
+#[test]
+fn tablebase_response() {
+    //moves in the server's order, with results from the opponent's side
+    let text = r#"{
+        "category": "win", "dtz": 1, "dtm": 1, "checkmate": false,
+        "moves": [
+            {"uci": "c6c7", "san": "Kc7", "category": "loss", "dtz": -2, "dtm": -4},
+            {"uci": "b6a6", "san": "Qa6", "category": "draw", "dtz": 0, "dtm": null},
+            {"uci": "b6b5", "san": "Qb5", "category": "loss", "dtz": -8, "dtm": -8},
+            {"uci": "b6b7", "san": "Qb7#", "category": "loss", "dtz": 0, "dtm": 0,
+             "checkmate": true, "zeroing": false},
+            {"uci": "b6c5", "category": "unknown"}
+        ]
+    }"#;
+    let mut response: TablebaseResponse = serde_json::from_str(text).unwrap();
+    assert_eq!(response.category.wdl(), Some(Wdl::Win));
+    assert_eq!((response.dtz, response.dtm), (Some(1), Some(1)));
+    assert_eq!(response.moves[1].wdl(), Some(Wdl::Draw));
+    assert_eq!(response.moves[4].wdl(), None);
+    //mate at once, then the fastest win
+    assert_eq!(response.best_move().unwrap().1.uci, "b6b7");
+    response.moves.remove(3);
+    assert_eq!(response.best_move().unwrap().1.uci, "c6c7");
+    //the slowest loss for the other side
+    for mv in response.moves.iter_mut() {
+        mv.category = Category::Win;
+    }
+    assert_eq!(
+        response.best_move().unwrap(),
+        (Wdl::Loss, &response.moves[2])
+    );
+
+    //distance to mate where every winning move has one, else to zeroing
+    let mut response: TablebaseResponse = serde_json::from_str(
+        r#"{"moves": [
+            {"uci": "c6c7", "category": "loss", "dtz": -2, "dtm": -10},
+            {"uci": "b6b5", "category": "loss", "dtz": -6, "dtm": -4},
+            {"uci": "b6a6", "category": "draw", "dtz": 0, "dtm": null}
+        ]}"#,
+    )
+    .unwrap();
+    assert_eq!(response.best_move().unwrap().1.uci, "b6b5");
+    response.moves[1].dtm = None;
+    assert_eq!(response.best_move().unwrap().1.uci, "c6c7");
+
+    //results as numbers, from older versions of the server
+    let old: TablebaseResponse =
+        serde_json::from_str(r#"{"wdl": 2, "moves": [{"uci": "b6b7", "wdl": -2}]}"#).unwrap();
+    assert_eq!(old.category, Category::Unknown);
+    assert_eq!(old.best_move().unwrap().0, Wdl::Win);
+}
+
+#[test]
+fn remote_tablebase_score() {
+    let board = Board::from_fen("k7/8/1QK5/8/8/8/8/8 w - - 0 1").unwrap();
+    let (url, server) = stub_server(vec![(
+        200,
+        r#"{"category":"win","dtz":1,"dtm":1,"moves":[
+            {"uci":"c6c7","category":"loss","dtz":-2,"dtm":-4},
+            {"uci":"b6b7","category":"loss","dtz":0,"dtm":0,"checkmate":true}]}"#,
+    )]);
+    let mut remote = RemoteTablebase::with_url(&url);
+    let result = remote.probe(&board).unwrap();
+    assert_eq!(
+        result,
+        ProbeResult {
+            wdl: Wdl::Win,
+            mv: mv("b6", "b7"),
+            dtz: Some(1),
+            dtm: Some(1),
+        }
+    );
+    server.join().unwrap();
+
+    //the result is the evaluation, a win sooner scoring higher
+    let mut computer = Computer::new(&OPENING_BOOK);
+    computer.following_opening = false;
+    computer.set_tablebase(Box::new(remote));
+    let (score, best) = computer
+        .get_move(&board, &None, SearchLimit::Depth(3))
+        .unwrap();
+    assert_eq!((score, best), (TABLEBASE_WIN - 1.0, mv("b6", "b7")));
+}
+
+lazy_static::lazy_static! {
+    //solving is slow, so the tests share one set of tables
+    static ref SOLVED: retrograde::RetrogradeTablebase = {
+        let mut tablebase = retrograde::RetrogradeTablebase::new();
+        tablebase.solve(&retrograde::KPK);
+        tablebase
+    };
+}
+
+#[test]
+fn retrograde_values() {
+    let lookup = |fen| SOLVED.lookup(&Board::from_fen(fen).unwrap());
+    //KPK needs the tables its promotions lead into
+    assert!(SOLVED.has(&retrograde::KQK) && SOLVED.has(&retrograde::KRK));
+    assert!(!SOLVED.has(&retrograde::KBNK));
+
+    assert_eq!(lookup("k7/8/1QK5/8/8/8/8/8 w - - 0 1"), Some((Wdl::Win, 1)));
+    assert_eq!(lookup("kQK5/8/8/8/8/8/8/8 b - - 0 1"), Some((Wdl::Loss, 0)));
+    assert_eq!(
+        lookup("k7/8/1Q6/8/8/8/8/7K b - - 0 1"),
+        Some((Wdl::Draw, 0))
+    );
+    //the same positions with the colours swapped
+    assert_eq!(lookup("8/8/8/8/8/1qk5/8/K7 b - - 0 1"), Some((Wdl::Win, 1)));
+    assert_eq!(
+        lookup("K7/8/1q6/8/8/8/8/7k w - - 0 1"),
+        Some((Wdl::Draw, 0))
+    );
+    //the longest KRK mate is 16 moves
+    assert_eq!(
+        lookup("8/8/8/3k4/8/8/8/KR6 w - - 0 1"),
+        Some((Wdl::Win, 29))
+    );
+
+    //a king in front of its pawn wins, one behind it against the opposition does not
+    assert_eq!(
+        lookup("4k3/8/4K3/4P3/8/8/8/8 w - - 0 1").unwrap().0,
+        Wdl::Win
+    );
+    assert_eq!(
+        lookup("4k3/8/4K3/4P3/8/8/8/8 b - - 0 1").unwrap().0,
+        Wdl::Loss
+    );
+    assert_eq!(
+        lookup("8/8/8/8/8/4k3/4P3/4K3 w - - 0 1"),
+        Some((Wdl::Draw, 0))
+    );
+    assert_eq!(lookup("4k3/8/8/8/8/8/8/KBN5 w - - 0 1"), None);
+    assert_eq!(lookup("4k3/8/8/8/8/8/8/4K2R w K - 0 1"), None);
+    assert_eq!(
+        lookup("4k3/8/8/8/8/8/8/KB6 w - - 0 1"),
+        Some((Wdl::Draw, 0))
+    );
+
+    let board = Board::from_fen("k7/8/1QK5/8/8/8/8/8 w - - 0 1").unwrap();
+    let result = SOLVED.best_move(&board).unwrap();
+    assert_eq!(
+        (result.wdl, result.mv, result.dtm),
+        (Wdl::Win, mv("b6", "b7"), Some(1))
+    );
+}
+
+#[test]
+fn retrograde_play() {
+    //the winning side keeps winning and gets closer to mate every move
+    let mut board = Board::from_fen("4k3/8/4K3/4P3/8/8/8/8 w - - 0 1").unwrap();
+    let (wdl, mut plies) = SOLVED.lookup(&board).unwrap();
+    assert_eq!(wdl, Wdl::Win);
+    while plies > 0 {
+        let result = SOLVED.best_move(&board).unwrap();
+        board.exec_move(&result.mv);
+        let (_, next) = SOLVED.lookup(&board).unwrap();
+        assert_eq!(next, plies - 1);
+        plies = next;
+    }
+    assert_eq!(board.outcome(), Outcome::Checkmate(Color::White));
+
+    //and the engine plays from the tables without searching
+    let board = Board::from_fen("k7/8/1QK5/8/8/8/8/8 w - - 0 1").unwrap();
+    let mut computer = Computer::new(&OPENING_BOOK);
+    computer.following_opening = false;
+    computer.set_tablebase(Box::new(SOLVED.clone()));
+    let (score, best) = computer
+        .get_move(&board, &None, SearchLimit::Depth(3))
+        .unwrap();
+    assert_eq!((score, best), (TABLEBASE_WIN - 1.0, mv("b6", "b7")));
+    assert_eq!(computer.nodes(), 0);
+}
+
+#[test]
+fn retrograde_files() {
+    let dir = fake_tablebase_dir("retrograde", &["KQK.chtb"]);
+    //the junk file is skipped
+    assert_eq!(retrograde::RetrogradeTablebase::open(&dir).len(), 0);
+    SOLVED.save(&dir).unwrap();
+    let loaded = retrograde::RetrogradeTablebase::open(&dir);
+    assert_eq!(loaded.len(), 3);
+    for fen in [
+        "8/8/8/3k4/8/8/8/KR6 w - - 0 1",
+        "4k3/8/4K3/4P3/8/8/8/8 b - - 0 1",
+        "8/8/8/8/8/4k3/4P3/4K3 w - - 0 1",
+    ] {
+        let board = Board::from_fen(fen).unwrap();
+        assert_eq!(loaded.lookup(&board), SOLVED.lookup(&board));
+    }
+    //a table is much smaller than a byte for each position
+    let size = std::fs::metadata(dir.join("KPK.chtb")).unwrap().len();
+    assert!(size < retrograde::KPK.size() as u64 / 2);
+
+    //a cut off file is not a table
+    let bytes = std::fs::read(dir.join("KRK.chtb")).unwrap();
+    std::fs::write(dir.join("KRK.chtb"), &bytes[..bytes.len() / 2]).unwrap();
+    assert_eq!(retrograde::RetrogradeTablebase::open(&dir).len(), 2);
+    std::fs::remove_dir_all(dir).unwrap();
 }
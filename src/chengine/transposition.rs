@@ -0,0 +1,57 @@
+use crate::chengine::*;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Bound {
+    Exact,
+    Lower,
+    Upper,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TableEntry {
+    pub key: u64,
+    pub depth: u8,
+    pub bound: Bound,
+    pub score: f32,
+    pub best: Option<Move>,
+}
+
+pub struct TranspositionTable {
+    entries: Vec<Option<TableEntry>>,
+}
+
+impl TranspositionTable {
+    pub fn new(megabytes: usize) -> TranspositionTable {
+        //largest power of two that fits, so indexing is a mask
+        let fits = (megabytes * 1024 * 1024 / std::mem::size_of::<Option<TableEntry>>()).max(1);
+        let len = 1 << (usize::BITS - 1 - fits.leading_zeros());
+        TranspositionTable {
+            entries: vec![None; len],
+        }
+    }
+
+    fn index(&self, key: u64) -> usize {
+        key as usize & (self.entries.len() - 1)
+    }
+
+    pub fn probe(&self, key: u64) -> Option<&TableEntry> {
+        self.entries[self.index(key)]
+            .as_ref()
+            .filter(|entry| entry.key == key)
+    }
+
+    pub fn store(&mut self, entry: TableEntry) {
+        let index = self.index(entry.key);
+        //keep a deeper result for the same position, anything else is replaced
+        if let Some(old) = &self.entries[index] {
+            if old.key == entry.key && old.depth > entry.depth {
+                return;
+            }
+        }
+        self.entries[index] = Some(entry);
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.iter_mut().for_each(|entry| *entry = None);
+    }
+}
@@ -0,0 +1,283 @@
+use crate::chengine::*;
+use std::io::BufRead;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+const NAME: &str = "chengine";
+const AUTHOR: &str = "CoderMuffin";
+
+//finds the legal move written in long algebraic notation, with its flags filled in
+pub fn parse_move(board: &Board, text: &str) -> Option<Move> {
+    let wanted = Move::from_uci(text)?;
+    board
+        .get_moves(board.side_to_move())
+        .into_iter()
+        .find(|mv| *mv == wanted)
+}
+
+//score as seen by the side to move, mates are counted in moves from their plies
+pub fn score_string(score: f32) -> String {
+    let plies = (CHECKMATE - score.abs()) as i32;
+    if score >= MATE_BOUND {
+        format!("mate {}", (plies + 1) / 2)
+    } else if score <= -MATE_BOUND {
+        format!("mate -{}", plies / 2)
+    } else {
+        format!("cp {}", score as i32)
+    }
+}
+
+//`position startpos|fen <fen> [moves ...]`, the leading `position` already removed
+pub fn parse_position(args: &str) -> Option<Board> {
+    let (setup, moves) = match args.split_once("moves") {
+        Some((setup, moves)) => (setup.trim(), moves),
+        None => (args.trim(), ""),
+    };
+    let mut board = match setup.split_once(' ') {
+        None if setup == "startpos" => Board::new(),
+        Some(("fen", fen)) => Board::from_fen(fen.trim()).ok()?,
+        _ => return None,
+    };
+    for text in moves.split_whitespace() {
+        let mv = parse_move(&board, text)?;
+        board.exec_move(&mv);
+    }
+    Some(board)
+}
+
+//`go ...` arguments, the leading `go` already removed
+pub fn parse_go(args: &str, color: Color) -> SearchLimit {
+    let mut tokens = args.split_whitespace();
+    let mut limit = SearchLimit::Infinite;
+    let (mut time, mut increment, mut moves_to_go) = (None, Duration::ZERO, None);
+    let millis = |value: Option<&str>| {
+        Duration::from_millis(
+            value
+                .and_then(|x| x.parse::<i64>().ok())
+                .unwrap_or(0)
+                .max(0) as u64,
+        )
+    };
+    while let Some(token) = tokens.next() {
+        match (token, color) {
+            ("depth", _) => {
+                if let Some(depth) = tokens.next().and_then(|x| x.parse().ok()) {
+                    limit = SearchLimit::Depth(depth);
+                }
+            }
+            ("nodes", _) => {
+                if let Some(nodes) = tokens.next().and_then(|x| x.parse().ok()) {
+                    limit = SearchLimit::Nodes(nodes);
+                }
+            }
+            ("movetime", _) => limit = SearchLimit::MoveTime(millis(tokens.next())),
+            ("wtime", Color::White) | ("btime", Color::Black) => time = Some(millis(tokens.next())),
+            ("winc", Color::White) | ("binc", Color::Black) => increment = millis(tokens.next()),
+            ("movestogo", _) => moves_to_go = tokens.next().and_then(|x| x.parse().ok()),
+            ("wtime" | "btime" | "winc" | "binc", _) => {
+                tokens.next();
+            }
+            _ => {}
+        }
+    }
+    match time {
+        Some(remaining) if limit == SearchLimit::Infinite => SearchLimit::Clock {
+            remaining,
+            increment,
+            moves_to_go,
+        },
+        _ => limit,
+    }
+}
+
+pub struct Uci {
+    board: Board,
+    //taken by the search thread and handed back when it finishes
+    computer: Option<Computer>,
+    search: Option<(Arc<AtomicBool>, JoinHandle<Computer>)>,
+    tablebase: TablebaseOptions,
+}
+
+impl Uci {
+    pub fn new() -> Uci {
+        let mut computer = Computer::new(&OPENING_BOOK);
+        computer.following_opening = false;
+        Uci {
+            board: Board::new(),
+            computer: Some(computer),
+            search: None,
+            tablebase: TablebaseOptions::default(),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    pub fn run(&mut self, input: impl BufRead) {
+        for line in input.lines() {
+            match line {
+                Ok(line) if self.handle(&line) => {}
+                _ => break,
+            }
+        }
+        self.stop();
+    }
+
+    //returns false once the engine should exit
+    pub fn handle(&mut self, line: &str) -> bool {
+        let line = line.trim();
+        let (command, args) = line.split_once(' ').unwrap_or((line, ""));
+        match command {
+            "uci" => {
+                println!("id name {}", NAME);
+                println!("id author {}", AUTHOR);
+                println!(
+                    "option name Hash type spin default {} min 1 max 1024",
+                    Computer::DEFAULT_TABLE_MB
+                );
+                println!("option name SyzygyPath type string default <empty>");
+                println!(
+                    "option name SyzygyProbeLimit type spin default {} min 0 max {}",
+                    Computer::MAX_TABLEBASE_PIECES,
+                    Computer::MAX_TABLEBASE_PIECES
+                );
+                println!("option name OnlineTablebase type check default false");
+                println!(
+                    "option name OnlineTablebaseUrl type string default {}",
+                    RemoteTablebase::LICHESS
+                );
+                println!(
+                    "option name OnlineTablebaseTimeout type spin default {} min 1 max 60000",
+                    RemoteTablebase::DEFAULT_TIMEOUT.as_millis()
+                );
+                println!("option name OnlineTablebaseCache type string default <empty>");
+                println!("uciok");
+            }
+            "isready" => println!("readyok"),
+            "ucinewgame" => {
+                self.computer().clear_table();
+                self.board = Board::new();
+            }
+            "setoption" => self.set_option(args),
+            "position" => match parse_position(args) {
+                Some(board) => {
+                    self.wait();
+                    self.board = board;
+                }
+                None => println!("info string invalid position '{}'", args),
+            },
+            "go" => self.go(args),
+            "stop" => self.stop(),
+            "quit" => {
+                self.stop();
+                return false;
+            }
+            _ => {}
+        }
+        true
+    }
+
+    //`name <id> value <x>`
+    fn set_option(&mut self, args: &str) {
+        let Some((name, value)) = args
+            .strip_prefix("name ")
+            .and_then(|x| x.split_once(" value "))
+        else {
+            return;
+        };
+        let value = value.trim();
+        match name.trim().to_ascii_lowercase().as_str() {
+            "hash" => match value.parse::<usize>() {
+                Ok(megabytes) => self.computer().set_table_size(megabytes.clamp(1, 1024)),
+                Err(..) => println!("info string invalid hash size '{}'", value),
+            },
+            "syzygypath" => {
+                let path = if value == "<empty>" { "" } else { value };
+                let count = self.computer().set_syzygy_path(path);
+                println!("info string found {} tablebases", count);
+            }
+            _ => {
+                self.wait();
+                let computer = self
+                    .computer
+                    .as_mut()
+                    .expect("Computer missing after search");
+                if let Some(message) = self.tablebase.set(computer, name, value) {
+                    println!("info string {}", message);
+                }
+            }
+        }
+    }
+
+    fn go(&mut self, args: &str) {
+        let limit = parse_go(args, self.board.side_to_move());
+        let board = self.board.clone();
+        if board.get_moves(board.side_to_move()).is_empty() {
+            println!("bestmove 0000");
+            return;
+        }
+
+        self.wait();
+        let mut computer = self.computer.take().expect("Computer missing after search");
+        let stop = computer.stop_handle();
+        stop.store(false, Ordering::Relaxed);
+        let thread_stop = stop.clone();
+        let handle = std::thread::spawn(move || {
+            let best = computer.search_with_info(&board, limit, |info| {
+                let secs = info.elapsed.as_secs_f64();
+                let nps = match secs > 0.0 {
+                    true => (info.nodes as f64 / secs) as u64,
+                    false => 0,
+                };
+                println!(
+                    "info depth {} score {} nodes {} nps {} time {} pv {}",
+                    info.depth,
+                    score_string(info.score),
+                    info.nodes,
+                    nps,
+                    info.elapsed.as_millis(),
+                    info.pv
+                        .iter()
+                        .map(|mv| mv.to_uci())
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                );
+            });
+            //an infinite search only reports its move once told to stop
+            if limit == SearchLimit::Infinite {
+                while !thread_stop.load(Ordering::Relaxed) {
+                    std::thread::sleep(Duration::from_millis(5));
+                }
+            }
+            let best = best.map_or("0000".to_string(), |(_, mv)| mv.to_uci());
+            println!("bestmove {}", best);
+            computer
+        });
+        self.search = Some((stop, handle));
+    }
+
+    fn stop(&mut self) {
+        if let Some((stop, _)) = &self.search {
+            stop.store(true, Ordering::Relaxed);
+        }
+        self.wait();
+    }
+
+    //blocks until the running search, if any, has printed its move
+    fn wait(&mut self) {
+        if let Some((_, handle)) = self.search.take() {
+            self.computer = Some(handle.join().expect("Search thread panicked"));
+        }
+    }
+
+    fn computer(&mut self) -> &mut Computer {
+        self.wait();
+        self.computer
+            .as_mut()
+            .expect("Computer missing after search")
+    }
+}
@@ -0,0 +1,261 @@
+use crate::chengine::*;
+use std::io::BufRead;
+use std::time::Duration;
+
+//used when the gui has not sent any time control
+const DEFAULT_MOVE_TIME: Duration = Duration::from_secs(5);
+
+//scores are in centipawns, mates use the usual 100000 + moves convention
+pub fn score_value(score: f32) -> i32 {
+    let plies = (CHECKMATE - score.abs()) as i32;
+    if score >= MATE_BOUND {
+        100000 + (plies + 1) / 2
+    } else if score <= -MATE_BOUND {
+        -100000 - plies / 2
+    } else {
+        score as i32
+    }
+}
+
+pub struct XBoard {
+    board: Board,
+    computer: Computer,
+    history: Vec<(Move, UndoInfo)>,
+    //in force mode both sides are played by the gui
+    force: bool,
+    engine_color: Color,
+    post: bool,
+    remaining: Option<Duration>,
+    increment: Duration,
+    moves_per_session: Option<u32>,
+    move_time: Option<Duration>,
+    max_depth: Option<u8>,
+    tablebase: TablebaseOptions,
+}
+
+impl XBoard {
+    pub fn new() -> XBoard {
+        let mut computer = Computer::new(&OPENING_BOOK);
+        computer.following_opening = false;
+        XBoard {
+            board: Board::new(),
+            computer,
+            history: Vec::new(),
+            force: false,
+            engine_color: Color::Black,
+            post: false,
+            remaining: None,
+            increment: Duration::ZERO,
+            moves_per_session: None,
+            move_time: None,
+            max_depth: None,
+            tablebase: TablebaseOptions::default(),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    pub fn run(&mut self, input: impl BufRead) {
+        for line in input.lines() {
+            match line {
+                Ok(line) if self.handle(&line) => {}
+                _ => break,
+            }
+        }
+    }
+
+    //returns false once the engine should exit
+    pub fn handle(&mut self, line: &str) -> bool {
+        let line = line.trim();
+        let (command, args) = line.split_once(' ').unwrap_or((line, ""));
+        let args = args.trim();
+        match command {
+            "protover" => {
+                println!(
+                    "feature myname=\"chengine\" usermove=1 setboard=1 ping=1 sigint=0 sigterm=0 colors=0 egt=\"syzygy\""
+                );
+                println!(
+                    "feature option=\"SyzygyProbeLimit -spin {} 0 {}\"",
+                    Computer::MAX_TABLEBASE_PIECES,
+                    Computer::MAX_TABLEBASE_PIECES
+                );
+                println!("feature option=\"OnlineTablebase -check 0\"");
+                println!(
+                    "feature option=\"OnlineTablebaseUrl -string {}\"",
+                    RemoteTablebase::LICHESS
+                );
+                println!(
+                    "feature option=\"OnlineTablebaseTimeout -spin {} 1 60000\"",
+                    RemoteTablebase::DEFAULT_TIMEOUT.as_millis()
+                );
+                println!("feature option=\"OnlineTablebaseCache -file \"");
+                println!("feature done=1");
+            }
+            "new" => {
+                self.board = Board::new();
+                self.history.clear();
+                self.force = false;
+                self.engine_color = Color::Black;
+                self.max_depth = None;
+                self.move_time = None;
+            }
+            "force" => self.force = true,
+            "go" => {
+                self.force = false;
+                self.engine_color = self.board.side_to_move();
+                self.think();
+            }
+            "playother" => {
+                self.force = false;
+                self.engine_color = !self.board.side_to_move();
+            }
+            "usermove" => self.user_move(args),
+            "undo" => {
+                self.undo();
+            }
+            //takes back a move for each side so the user is on move again
+            "remove" if self.undo() => {
+                self.undo();
+            }
+            "setboard" => match Board::from_fen(args) {
+                Ok(board) => {
+                    self.board = board;
+                    self.history.clear();
+                }
+                Err(err) => println!("tellusererror Illegal position: {}", err),
+            },
+            "time" => self.remaining = Self::centiseconds(args),
+            //the opponent clock plays no part in how long we think
+            "otim" => {}
+            "level" => self.level(args),
+            "st" => self.move_time = args.parse().ok().map(Duration::from_secs),
+            "sd" => self.max_depth = args.parse().ok(),
+            "post" => self.post = true,
+            "nopost" => self.post = false,
+            "ping" => println!("pong {}", args),
+            "egtpath" => {
+                if let Some(path) = args.strip_prefix("syzygy") {
+                    self.computer.set_syzygy_path(path.trim());
+                }
+            }
+            //`option <name>=<value>` for the options announced in protover
+            "option" => {
+                if let Some((name, value)) = args.split_once('=') {
+                    if let Some(message) = self.tablebase.set(&mut self.computer, name, value) {
+                        println!("# {}", message);
+                    }
+                }
+            }
+            "quit" => return false,
+            _ => {}
+        }
+        true
+    }
+
+    fn centiseconds(args: &str) -> Option<Duration> {
+        args.parse::<i64>()
+            .ok()
+            .map(|cs| Duration::from_millis(cs.max(0) as u64 * 10))
+    }
+
+    //`level <moves per session> <base minutes[:seconds]> <increment seconds>`
+    fn level(&mut self, args: &str) {
+        let mut fields = args.split_whitespace();
+        self.moves_per_session = fields
+            .next()
+            .and_then(|x| x.parse().ok())
+            .filter(|&x| x > 0);
+        fields.next();
+        self.increment = fields
+            .next()
+            .and_then(|x| x.parse::<f64>().ok())
+            .map(|x| Duration::from_secs_f64(x.max(0.0)))
+            .unwrap_or(Duration::ZERO);
+        self.move_time = None;
+    }
+
+    fn limit(&self) -> SearchLimit {
+        if let Some(depth) = self.max_depth {
+            return SearchLimit::Depth(depth);
+        }
+        if let Some(time) = self.move_time {
+            return SearchLimit::MoveTime(time);
+        }
+        match self.remaining {
+            Some(remaining) => SearchLimit::Clock {
+                remaining,
+                increment: self.increment,
+                moves_to_go: self
+                    .moves_per_session
+                    .map(|mps| mps - (self.board.fullmove_number().saturating_sub(1) as u32 % mps)),
+            },
+            None => SearchLimit::MoveTime(DEFAULT_MOVE_TIME),
+        }
+    }
+
+    fn user_move(&mut self, text: &str) {
+        let Some(mv) = uci::parse_move(&self.board, text) else {
+            println!("Illegal move: {}", text);
+            return;
+        };
+        self.history.push((mv, self.board.exec_move(&mv)));
+        if !self.report_result() && !self.force && self.board.side_to_move() == self.engine_color {
+            self.think();
+        }
+    }
+
+    fn undo(&mut self) -> bool {
+        match self.history.pop() {
+            Some((mv, undo)) => {
+                self.board.unexec_move(&mv, undo);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn think(&mut self) {
+        let post = self.post;
+        let limit = self.limit();
+        let best = self.computer.search_with_info(&self.board, limit, |info| {
+            if post {
+                println!(
+                    "{} {} {} {} {}",
+                    info.depth,
+                    score_value(info.score),
+                    info.elapsed.as_millis() / 10,
+                    info.nodes,
+                    info.pv
+                        .iter()
+                        .map(|mv| mv.to_uci())
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                );
+            }
+        });
+        //with no legal moves the game is already over
+        let Some((_, mv)) = best else {
+            self.report_result();
+            return;
+        };
+        self.history.push((mv, self.board.exec_move(&mv)));
+        println!("move {}", mv.to_uci());
+        self.report_result();
+    }
+
+    //tells the gui when the game is over, returning whether it is
+    fn report_result(&self) -> bool {
+        let result = match self.board.outcome() {
+            Outcome::Ongoing => return false,
+            Outcome::Checkmate(Color::White) => "1-0 {White mates}".to_string(),
+            Outcome::Checkmate(Color::Black) => "0-1 {Black mates}".to_string(),
+            Outcome::Stalemate => "1/2-1/2 {Stalemate}".to_string(),
+            Outcome::Draw(reason) => format!("1/2-1/2 {{{:?}}}", reason),
+        };
+        println!("{}", result);
+        true
+    }
+}
@@ -0,0 +1,49 @@
+use crate::chengine::*;
+
+//splitmix64, so the keys are fixed at compile time and hashes are stable between runs
+const fn keys<const N: usize>(seed: u64) -> [u64; N] {
+    let mut keys = [0; N];
+    let mut state = seed;
+    let mut i = 0;
+    while i < N {
+        state = state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        keys[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    keys
+}
+
+const PIECE_KEYS: [u64; 12 * 64] = keys(1);
+const CASTLE_KEYS: [u64; 4] = keys(2);
+const EN_PASSANT_KEYS: [u64; 8] = keys(3);
+pub const SIDE: u64 = keys::<1>(4)[0];
+
+pub fn piece(piece: &Piece, square: &Square) -> u64 {
+    let kind = piece.kind.index() + bitboard::side(piece.color) * 6;
+    PIECE_KEYS[kind * 64 + bitboard::index(square)]
+}
+
+pub fn castling(white: &CastleInfo, black: &CastleInfo) -> u64 {
+    let mut key = 0;
+    for (i, right) in [
+        white.kingside,
+        white.queenside,
+        black.kingside,
+        black.queenside,
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        if right {
+            key ^= CASTLE_KEYS[i];
+        }
+    }
+    key
+}
+
+pub fn en_passant(square: &Square) -> u64 {
+    EN_PASSANT_KEYS[square.x as usize]
+}
@@ -7,77 +7,197 @@ static DEPTH: u8 = 5;
 static PERSPECTIVE: Color = Color::Black;
 
 enum InputResult {
-    Move((Square, Square)),
+    Played(Move, String),
     Undo,
-    NoChange
+    NoChange,
+    Uci,
+    XBoard,
 }
 use InputResult::*;
 
 fn input_move(
     board: &mut Board,
-    color: Color,
     stdin: &std::io::Stdin,
-    last_move: &Option<(Square, Square)>,
+    last_move: &Option<Move>,
     computers: (&mut Computer, &mut Computer),
-    moves: &mut Vec<(Square, Square, MoveData)>,
+    moves: &mut Vec<(Move, UndoInfo)>,
 ) -> Option<InputResult> {
     board.display(PERSPECTIVE);
+    let color = board.side_to_move();
     let mut line_buf = String::new();
     stdin.read_line(&mut line_buf).ok()?;
     let mut iter = line_buf.split(" ");
     match iter.next()?.trim() {
         "go" => {
             let now = std::time::Instant::now();
-            let to_move = match color {
-                Color::White => computers.0.get_move(board, last_move, DEPTH),
-                Color::Black => computers.1.get_move(board, last_move, DEPTH),
+            let computer = match color {
+                Color::White => computers.0,
+                Color::Black => computers.1,
+            };
+            let Some(to_move) = computer.get_move(board, last_move, SearchLimit::Depth(DEPTH))
+            else {
+                println!("No legal moves, the game is over");
+                return Some(NoChange);
             };
             println!(
-                "Found move\nMinimum value: {}\nDepth: {}\nTime: {:.2?}",
+                "Found move\nMinimum value: {}\nDepth: {}\nNodes: {}\nTime: {:.2?}",
                 to_move.0,
-                DEPTH,
+                computer.completed_depth(),
+                computer.nodes(),
                 now.elapsed()
             );
-            moves.push((
-                to_move.1 .0,
-                to_move.1 .1,
-                board.exec_move(&to_move.1 .0, &to_move.1 .1),
-            ));
-            board.highlight_move = to_move.1;
-            Some(Move(to_move.1))
+            let notation = san::to_san(board, &to_move.1);
+            moves.push((to_move.1, board.exec_move(&to_move.1)));
+            board.highlight_move = (to_move.1.from, to_move.1.to);
+            Some(Played(to_move.1, notation))
         }
         "move" => {
             let from = Square::new(iter.next()?.trim())?;
             let to = Square::new(iter.next()?.trim())?;
+            //promotion piece is optional and defaults to a queen
+            let promotion = match iter.next().map(|x| x.trim()) {
+                Some(id) if !id.is_empty() => Some(PieceKind::from_char(id.chars().next()?)?),
+                _ => None,
+            };
 
             let mut valid_moves = board.get_moves(color);
             board.filter_checks(&mut valid_moves, color);
-            if valid_moves.iter().any(|x| *x == (from, to)) {
-                moves.push((from, to, board.exec_move(&from, &to)));
-                board.highlight_move = (from, to);
-                Some(Move((from, to)))
-            } else {
-                None
-            }
+            let new_move = *valid_moves.iter().find(|x| {
+                x.from == from
+                    && x.to == to
+                    && (x.promotion == promotion
+                        || (promotion.is_none() && x.promotion == Some(PieceKind::Queen)))
+            })?;
+            let notation = san::to_san(board, &new_move);
+            moves.push((new_move, board.exec_move(&new_move)));
+            board.highlight_move = (from, to);
+            Some(Played(new_move, notation))
         }
+        "uci" => Some(Uci),
+        "xboard" => Some(XBoard),
         "query" => {
             let sq = Square::new(iter.next()?.trim())?;
             board.highlight_piece = Some(sq);
 
             Some(NoChange)
         }
+        "save" => {
+            //walk back to the first position so games loaded from a FEN keep it
+            let mut start = board.clone();
+            for (old_move, undo) in moves.iter().rev() {
+                start.unexec_move(old_move, *undo);
+            }
+            let mut game = pgn::Game::new();
+            if start.fen() != Board::new().fen() {
+                game.set_tag("SetUp", "1");
+                game.set_tag("FEN", &start.fen());
+            }
+            game.moves = moves.iter().map(|x| x.0).collect();
+            game.set_result(pgn::Game::result_of(board.outcome()));
+            std::fs::write(iter.next()?.trim(), game.to_pgn().ok()?).ok()?;
+            Some(NoChange)
+        }
+        "load" => {
+            let text = std::fs::read_to_string(iter.next()?.trim()).ok()?;
+            let game = pgn::Game::read(&text).ok()?.into_iter().next()?;
+            *board = game.start().ok()?;
+            moves.clear();
+            for new_move in game.moves {
+                moves.push((new_move, board.exec_move(&new_move)));
+            }
+            //the book cannot follow a game it did not see being played
+            computers.0.following_opening = false;
+            computers.1.following_opening = false;
+            Some(NoChange)
+        }
+        "syzygy" => {
+            let path = iter.collect::<Vec<_>>().join(" ");
+            let count = computers.0.set_syzygy_path(path.trim());
+            computers.1.set_syzygy_path(path.trim());
+            println!("Found {} tablebases", count);
+            Some(NoChange)
+        }
+        //`online on [url] [timeout ms] [cache file]` looks endgames up on the lichess
+        //tablebase server, or another at the url given, instead of local tables
+        "online" => {
+            let enabled = iter.next()?.trim() == "on";
+            let mut settings = RemoteSettings::default();
+            if let Some(url) = iter.next().map(|x| x.trim()).filter(|x| !x.is_empty()) {
+                settings.url = url.to_string();
+            }
+            if let Some(timeout) = iter.next().map(|x| x.trim()).filter(|x| !x.is_empty()) {
+                settings.timeout = std::time::Duration::from_millis(timeout.parse().ok()?);
+            }
+            let cache_file = iter.collect::<Vec<_>>().join(" ");
+            if !cache_file.trim().is_empty() {
+                settings.cache_file = Some(cache_file.trim().into());
+            }
+            if !enabled {
+                computers.0.set_online_tablebase(None);
+                computers.1.set_online_tablebase(None);
+                return Some(NoChange);
+            }
+            let remote = settings.connect();
+            println!(
+                "Online tablebase at {}, {} cached positions",
+                settings.url,
+                remote.cached()
+            );
+            computers.0.set_online_tablebase(Some(Box::new(remote)));
+            computers
+                .1
+                .set_online_tablebase(Some(Box::new(settings.connect())));
+            Some(NoChange)
+        }
+        //the most pieces a position may have to be looked up in a tablebase
+        "tbpieces" => {
+            let pieces: u8 = iter.next()?.trim().parse().ok()?;
+            let pieces = pieces.min(Computer::MAX_TABLEBASE_PIECES);
+            computers.0.tablebase_pieces = pieces;
+            computers.1.tablebase_pieces = pieces;
+            println!("Tablebases probed with up to {} pieces", pieces);
+            Some(NoChange)
+        }
+        //works out the small endings that can be played perfectly, keeping the
+        //tables in the directory given so later runs only have to load them
+        "solve" => {
+            let dir = iter.collect::<Vec<_>>().join(" ");
+            let dir = std::path::Path::new(dir.trim());
+            let mut tablebase = retrograde::RetrogradeTablebase::open(dir);
+            for ending in retrograde::ENDINGS {
+                if !tablebase.has(ending) {
+                    println!("Solving {}", ending.name);
+                    tablebase.solve(ending);
+                }
+            }
+            if let Err(err) = tablebase.save(dir) {
+                println!("Could not save tables: {}", err);
+            }
+            println!("{} endgame tables ready", tablebase.len());
+            computers.0.set_tablebase(Box::new(tablebase.clone()));
+            computers.1.set_tablebase(Box::new(tablebase));
+            Some(NoChange)
+        }
         "undo" => {
             if let Some(old_move) = moves.pop() {
-                board.unexec_move(&old_move.0, &old_move.1, old_move.2);
+                board.unexec_move(&old_move.0, old_move.1);
                 Some(Undo)
             } else {
                 Some(NoChange)
             }
         }
-        _ => None,
+        //anything else is tried as a move in algebraic notation, e.g. Nf3
+        text => {
+            let new_move = san::parse_san(board, text)?;
+            let notation = san::to_san(board, &new_move);
+            moves.push((new_move, board.exec_move(&new_move)));
+            board.highlight_move = (new_move.from, new_move.to);
+            Some(Played(new_move, notation))
+        }
     }
 }
 
+#[allow(dead_code)]
 trait StdinExtension {
     fn wait_for_enter(&self) -> std::io::Result<()>;
 }
@@ -90,30 +210,112 @@ impl StdinExtension for std::io::Stdin {
     }
 }
 
+//`perft <depth> [fen]`, printing the count under each first move like other engines do
+fn perft(args: Vec<String>) {
+    let Some(depth) = args.first().and_then(|x| x.parse().ok()) else {
+        println!("Usage: perft <depth> [fen]");
+        return;
+    };
+    let mut board = match args.len() > 1 {
+        true => match Board::from_fen(&args[1..].join(" ")) {
+            Ok(board) => board,
+            Err(err) => {
+                println!("Invalid FEN: {}", err);
+                return;
+            }
+        },
+        false => Board::new(),
+    };
+    let now = std::time::Instant::now();
+    let divide = board.divide(depth);
+    let elapsed = now.elapsed();
+    for (mv, nodes) in &divide {
+        println!("{}: {}", mv.to_uci(), nodes);
+    }
+    let nodes: u64 = divide.iter().map(|x| x.1).sum();
+    println!(
+        "\nNodes: {}\nTime: {:.2?}\nNodes/s: {:.0}",
+        nodes,
+        elapsed,
+        nodes as f64 / elapsed.as_secs_f64()
+    );
+}
+
+//fixed depth searches of well known positions, for comparing search speed between builds
+fn bench() {
+    let positions = [
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+    ];
+    let mut total_nodes = 0;
+    let mut total_time = std::time::Duration::ZERO;
+    for fen in positions {
+        let board = Board::from_fen(fen).expect("bench positions are valid");
+        let mut computer = Computer::new(&OPENING_BOOK);
+        let now = std::time::Instant::now();
+        let (_, best) = computer
+            .search(&board, SearchLimit::Depth(DEPTH))
+            .expect("bench positions have legal moves");
+        let elapsed = now.elapsed();
+        println!(
+            "{}\n  best {} nodes {} time {:.2?}",
+            fen,
+            best.to_uci(),
+            computer.nodes(),
+            elapsed
+        );
+        total_nodes += computer.nodes();
+        total_time += elapsed;
+    }
+    println!(
+        "\nNodes: {}\nTime: {:.2?}\nNodes/s: {:.0}",
+        total_nodes,
+        total_time,
+        total_nodes as f64 / total_time.as_secs_f64()
+    );
+}
+
 fn main() -> std::io::Result<()> {
     let mut board = Board::new();
-    let mut current_color = Color::White;
     let stdin = std::io::stdin();
+    match std::env::args().nth(1).as_deref() {
+        Some("uci") => {
+            uci::Uci::new().run(stdin.lock());
+            return Ok(());
+        }
+        Some("xboard") => {
+            xboard::XBoard::new().run(stdin.lock());
+            return Ok(());
+        }
+        Some("bench") => {
+            bench();
+            return Ok(());
+        }
+        Some("perft") => {
+            perft(std::env::args().skip(2).collect());
+            return Ok(());
+        }
+        _ => {}
+    }
     let mut last_move = None;
 
     let mut moves = Vec::new();
-    let mut computer_white: Computer = Computer::new(Color::White, &OPENING_BOOK);
-    let mut computer_black: Computer = Computer::new(Color::Black, &OPENING_BOOK);
+    let mut computer_white: Computer = Computer::new(&OPENING_BOOK);
+    let mut computer_black: Computer = Computer::new(&OPENING_BOOK);
 
     loop {
         match input_move(
             &mut board,
-            current_color,
             &stdin,
             &last_move,
             (&mut computer_white, &mut computer_black),
             &mut moves,
         ) {
-            Some(Move(new_move)) => {
-                    println!(
-                    "Move: {} to {}\nEval (+white, -black): {}\nWhite in check: {}\nBlack in check: {}",
-                    new_move.0,
-                    new_move.1,
+            Some(Played(new_move, notation)) => {
+                println!(
+                    "Move: {}\nEval (+white, -black): {}\nWhite in check: {}\nBlack in check: {}",
+                    notation,
                     board.eval(Color::White),
                     board.king_in_check(Color::White),
                     board.king_in_check(Color::Black),
@@ -121,19 +323,38 @@ fn main() -> std::io::Result<()> {
 
                 last_move = Some(new_move);
 
-                if board.eval(Color::White) == CHECKMATE {
-                    println!("White wins");
-                    break;
-                } else if board.eval(Color::Black) == CHECKMATE {
-                    println!("Black wins");
-                    break;
+                match board.outcome() {
+                    Outcome::Ongoing => {}
+                    Outcome::Checkmate(Color::White) => {
+                        println!("White wins");
+                        break;
+                    }
+                    Outcome::Checkmate(Color::Black) => {
+                        println!("Black wins");
+                        break;
+                    }
+                    Outcome::Stalemate => {
+                        println!("Draw by stalemate");
+                        break;
+                    }
+                    Outcome::Draw(reason) => {
+                        println!("Draw ({:?})", reason);
+                        break;
+                    }
                 }
-                current_color = !current_color;
-            },
-            Some(Undo) => {
-                current_color = !current_color;
             }
-            Some(NoChange) => {},
+            Some(Uci) => {
+                //a gui that sent `uci` gets the rest of the session in that protocol
+                let mut uci = uci::Uci::new();
+                uci.handle("uci");
+                uci.run(stdin.lock());
+                break;
+            }
+            Some(XBoard) => {
+                xboard::XBoard::new().run(stdin.lock());
+                break;
+            }
+            Some(Undo) | Some(NoChange) => {}
             None => {
                 println!("Error");
             }